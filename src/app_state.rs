@@ -1,38 +1,65 @@
-use crate::dto::{ClientId, GameId, GameStatus, ShipsRaw, WsEvent};
+use crate::bot::BotLevel;
+use crate::config::ServerConfig;
+use crate::dto::{ClientId, GameId, GameResult, ShipsRaw, WsEvent};
+use crate::engine::{Action, GameCore, GameEvent, RuleError};
+use crate::error::ServerError;
+use crate::notation;
+use crate::rules::RuleSet;
+use crate::storage::{FinishedGame, SavedGame, Storage, Write, Writer};
+use crate::tournament::{FleetRequest, Standings};
 use rand::distr::{Alphanumeric, SampleString};
-use rand::Rng;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 use std::collections::{HashMap, HashSet, VecDeque};
-use std::sync::{Arc, Mutex, RwLock};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::sync::broadcast;
 use tokio::sync::mpsc::Sender;
 
 #[derive(Debug)]
 pub struct GameClients(pub(crate) ClientId, pub(crate) ClientId);
 
+impl GameClients {
+    /// The other seat of the game
+    pub fn opponent(&self, client_id: &str) -> &ClientId {
+        if self.0 == client_id {
+            &self.1
+        } else {
+            &self.0
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Wrapper {
     pub shared: Arc<Shared>,
 }
 
 impl Wrapper {
-    pub fn create_game(&self) -> String {
-        let mut state = self.shared.state.write().unwrap();
-
-        let game = Game::new();
-        let game_id = game.id.clone();
-
-        state.games.insert(game_id.clone(), game);
-
-        game_id
-    }
-
-    pub fn is_game_over(&self, game_id: &str) -> bool {
-        let state = self.shared.state.read().unwrap();
-        if let Some(g) = state.games.get(game_id) {
-            return g.status == GameStatus::GameOver;
+    /// Empty server state, game seeds from `GAME_SEED` if it is set
+    pub fn new(config: ServerConfig, storage: Arc<dyn Storage>) -> Self {
+        let writer = Writer::spawn(storage.clone());
+        let seeds = config
+            .game_seed
+            .map_or_else(StdRng::from_os_rng, StdRng::seed_from_u64);
+        Self {
+            shared: Arc::new(Shared {
+                state: RwLock::new(MyState {
+                    games: HashMap::new(),
+                    client_games: HashMap::new(),
+                    game_clients: HashMap::new(),
+                    queue: VecDeque::with_capacity(100),
+                    bots: HashMap::new(),
+                    tournament: None,
+                    fleets: HashMap::new(),
+                    players: HashSet::new(),
+                    seeds,
+                }),
+                storage,
+                writer,
+                config,
+            }),
         }
-
-        return false;
     }
 
     pub fn attach_client(&self, game_id: &str, client: Client) {
@@ -45,28 +72,159 @@ impl Wrapper {
 
             if r.client2.is_none() {
                 r.client2 = Some(client);
-                return;
             }
         }
     }
 
-    pub fn get_clients(&self, game_id: &str) -> (Client, Client) {
+    /// Re-binds a player who dropped mid-game to a new socket, returns the game to resume
+    pub fn reconnect_client(&self, client_id: &str, sender: Sender<WsEvent>) -> Option<GameId> {
+        let state = &mut self.shared.state.write().unwrap();
+        let game_id = state.client_games.get(client_id)?.clone();
+        let game = state.games.get_mut(&game_id)?;
+        game.disconnected.remove(client_id)?;
+
+        for client in [game.client1.as_mut(), game.client2.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            if client.id == client_id {
+                client.sender = sender.clone();
+            }
+        }
+
+        Some(game_id)
+    }
+
+    /// Reserves the player id for one socket, a resume token carries it and the player's
+    /// record over to the next connection. False if it is seated, queued or taken.
+    pub fn claim_player(&self, player_id: &str) -> bool {
+        let state = &mut self.shared.state.write().unwrap();
+        let busy = state.client_games.contains_key(player_id)
+            || state.bots.contains_key(player_id)
+            || state.queue.iter().any(|e| e.client_id == player_id);
+        !busy && state.players.insert(player_id.to_string())
+    }
+
+    /// Puts an authenticated bot in the lobby, false if one with that name is already there
+    pub fn register_bot(&self, name: &str, sender: Sender<WsEvent>) -> bool {
+        let state = &mut self.shared.state.write().unwrap();
+        if state.bots.contains_key(name) {
+            return false;
+        }
+        state.bots.insert(name.to_string(), sender);
+        true
+    }
+
+    /// Drops the game and every index pointing at it, returns its clients.
+    /// A finished game stays available as a replay.
+    pub fn remove_game(&self, game_id: &str) -> Vec<Client> {
+        let state = &mut self.shared.state.write().unwrap();
+        state.game_clients.remove(game_id);
+        let Some(game) = state.games.remove(game_id) else {
+            return vec![];
+        };
+        self.shared
+            .writer
+            .send(Write::Close(game_id.to_string(), game.notation()));
+
+        for p in [game.core.p1, game.core.p2].into_iter().flatten() {
+            if state
+                .client_games
+                .get(&p.name)
+                .is_some_and(|g| g == game_id)
+            {
+                state.client_games.remove(&p.name);
+            }
+        }
+
+        [game.client1, game.client2].into_iter().flatten().collect()
+    }
+
+    /// Seed for the next game
+    pub fn next_seed(&self) -> u64 {
+        let state = &mut self.shared.state.write().unwrap();
+        state.seeds.random()
+    }
+
+    /// The id is used by a game on the server or in storage
+    pub fn game_id_taken(&self, game_id: &str) -> bool {
+        if self
+            .shared
+            .state
+            .read()
+            .unwrap()
+            .games
+            .contains_key(game_id)
+        {
+            return true;
+        }
+        self.shared.storage.has_game(game_id).unwrap_or_else(|e| {
+            println!("Storage: game {} not looked up: {}", game_id, e);
+            false
+        })
+    }
+
+    /// Notation of a finished game. A running one is not shown, its fleets are still hidden.
+    pub fn replay(&self, game_id: &str) -> Option<String> {
+        let state = self.shared.state.read().unwrap();
+        if let Some(game) = state.games.get(game_id) {
+            let over = game.core.result.is_some();
+            return over.then(|| notation::export(&game.id, game.created, &game.core));
+        }
+        self.shared.storage.replay(game_id).unwrap_or_else(|e| {
+            println!("Storage: replay {} not loaded: {}", game_id, e);
+            None
+        })
+    }
+
+    /// Round the game is in, starting at 1
+    pub fn round(&self, game_id: &str) -> Option<u32> {
+        let state = self.shared.state.read().unwrap();
+        Some(state.games.get(game_id)?.core.round)
+    }
+
+    pub fn get_result(&self, game_id: &str) -> Option<GameResult> {
         let state = self.shared.state.read().unwrap();
-        let g = state.games.get(game_id).unwrap();
+        state.games.get(game_id)?.core.result.clone()
+    }
 
-        (g.client1.clone().unwrap(), g.client2.clone().unwrap())
+    pub fn get_clients(&self, game_id: &str) -> Result<(Client, Client), ServerError> {
+        let state = self.shared.state.read().unwrap();
+        let g = state
+            .games
+            .get(game_id)
+            .ok_or_else(|| ServerError::NoSuchGame(game_id.to_string()))?;
+
+        match (&g.client1, &g.client2) {
+            (Some(c1), Some(c2)) => Ok((c1.clone(), c2.clone())),
+            _ => Err(ServerError::Internal(format!(
+                "game {} is missing a client",
+                game_id
+            ))),
+        }
     }
 
-    pub fn get_room_sender(&self, game_id: &str) -> broadcast::Sender<String> {
+    pub fn get_room_sender(&self, game_id: &str) -> Option<broadcast::Sender<WsEvent>> {
         let state = self.shared.state.read().unwrap();
-        let r = state.games.get(game_id).unwrap();
-        r.room_sender.clone()
+        let r = state.games.get(game_id)?;
+        Some(r.room_sender.clone())
+    }
+
+    /// Sends to everyone spectating the game, nobody listening is fine
+    pub fn broadcast(&self, game_id: &str, event: WsEvent) {
+        if let Some(room) = self.get_room_sender(game_id) {
+            let _ = room.send(event);
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct Shared {
     pub state: RwLock<MyState>,
+    pub config: ServerConfig,
+    /// Reads only, changes go through `writer`
+    pub storage: Arc<dyn Storage>,
+    pub writer: Writer,
 }
 
 #[derive(Debug)]
@@ -74,90 +232,129 @@ pub struct MyState {
     pub games: HashMap<GameId, Game>,
     pub game_clients: HashMap<GameId, GameClients>,
     pub client_games: HashMap<ClientId, GameId>,
-    pub queue: VecDeque<(ClientId, Sender<WsEvent>, ShipsRaw)>,
+    pub queue: VecDeque<QueueEntry>,
+    /// Connected external bots, the tournament seats them
+    pub bots: HashMap<ClientId, Sender<WsEvent>>,
+    pub tournament: Option<Standings>,
+    /// Tournament bots asked for a fleet, see `FleetRq`
+    pub fleets: HashMap<ClientId, FleetRequest>,
+    /// Player ids held by a connected socket outside a game, see `claim_player`
+    pub players: HashSet<ClientId>,
+    /// Hands out game seeds, fixed by `GAME_SEED` to reproduce a whole run
+    pub seeds: StdRng,
+}
+
+#[derive(Debug)]
+pub struct QueueEntry {
+    pub client_id: ClientId,
+    pub sender: Sender<WsEvent>,
+    pub ships: ShipsRaw,
+    pub rules: RuleSet,
+    pub queued_at: Instant,
 }
 
 #[derive(Debug)]
 pub struct Game {
     pub id: String,
-    pub p1: Option<Player>,
-    pub p2: Option<Player>,
-    pub current_turn: String,
-    pub status: GameStatus,
-    pub room_sender: broadcast::Sender<String>,
+    pub created: SystemTime,
+    /// Rules and boards, everything else here is about the connections
+    pub core: GameCore,
+    /// Players whose socket dropped, with the moment they left
+    pub disconnected: HashMap<ClientId, Instant>,
+    /// Spectators of the game, see `SpectateRq`
+    pub room_sender: broadcast::Sender<WsEvent>,
     pub client1: Option<Client>,
     pub client2: Option<Client>,
+    /// Server side bot holding a seat, spawned again after a restart
+    pub bot: Option<(ClientId, BotLevel)>,
+}
+
+/// Mixed into the seed for the game id, the id is public and must not give away
+/// the draws of the game's own rng
+const GAME_ID_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
+
+fn game_id(seed: u64) -> GameId {
+    Alphanumeric.sample_string(&mut StdRng::seed_from_u64(seed ^ GAME_ID_SALT), 6)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
 
 impl Game {
-    pub fn new() -> Self {
+    pub fn new(rules: RuleSet, seed: u64) -> Self {
         let (tx, _) = broadcast::channel(16);
         Self {
-            p1: None,
-            p2: None,
+            id: game_id(seed),
+            created: SystemTime::now(),
+            core: GameCore::new(rules, seed),
+            disconnected: HashMap::new(),
             client1: None,
             client2: None,
             room_sender: tx,
-            current_turn: String::new(),
-            status: GameStatus::WaitingPlayers,
-            id: Alphanumeric.sample_string(&mut rand::rng(), 6),
+            bot: None,
         }
     }
 
-    pub fn join(&mut self, name: String, ships: ShipsRaw) {
-        let mut server_ships = Vec::new();
-        for ship in ships {
-            let mut ship_coords = vec![];
-            let mut ship_size = 0;
-            for coords in ship.into_iter() {
-                let p = Point2d::new(coords.0, coords.1);
-                ship_coords.push(p);
-                ship_size += 1;
-            }
-            server_ships.push(Ship::new(
-                ship_coords,
-                match ship_size {
-                    1 => ShipType::OneDeck1,
-                    2 => ShipType::TwoDeck1,
-                    3 => ShipType::ThreeDeck1,
-                    4 => ShipType::FourDeck1,
-                    _ => ShipType::OneDeck1,
-                },
-            ))
+    /// Game from storage, without clients until its players are seated again
+    pub fn restore(saved: SavedGame) -> Self {
+        let (tx, _) = broadcast::channel(16);
+        Self {
+            id: saved.id,
+            created: UNIX_EPOCH + Duration::from_secs(saved.created),
+            core: GameCore::restore(saved.rules, saved.seed, &saved.log).with_clocks(&saved.clocks),
+            disconnected: HashMap::new(),
+            client1: None,
+            client2: None,
+            room_sender: tx,
+            bot: saved.bot,
         }
+    }
 
-        let (mut cnt1, mut cnt2, mut cnt3, mut cnt4, mut err) = (0, 0, 0, 0, 0);
-        for ship in server_ships.iter() {
-            match ship.coords.len() {
-                1 => cnt1 += 1,
-                2 => cnt2 += 1,
-                3 => cnt3 += 1,
-                4 => cnt4 += 1,
-                _ => err += 1,
-            }
-        }
+    /// Notation kept as the replay, only a finished game has one
+    pub fn notation(&self) -> Option<String> {
+        self.core
+            .result
+            .is_some()
+            .then(|| notation::export(&self.id, self.created, &self.core))
+    }
 
-        if err != 0 || cnt1 != 4 || cnt2 != 3 || cnt3 != 2 || cnt4 != 1 {
-            println!("Error! Incorrect numbr fo ships");
-            return;
+    pub fn saved(&self) -> SavedGame {
+        SavedGame {
+            id: self.id.clone(),
+            created: unix_secs(self.created),
+            rules: self.core.rules.clone(),
+            seed: self.core.seed,
+            bot: self.bot.clone(),
+            log: self.core.log.clone(),
+            clocks: self.core.clocks(),
         }
+    }
 
-        if self.p1.is_none() {
-            self.p1 = Some(Player::new(name, server_ships));
-            return;
+    /// Applies the action and queues what it changed for storage
+    pub fn apply(&mut self, action: Action, writer: &Writer) -> Result<Vec<GameEvent>, RuleError> {
+        let events = self.core.apply(action)?;
+        if events.is_empty() {
+            return Ok(events);
         }
 
-        if self.p2.is_none() {
-            self.p2 = Some(Player::new(name, server_ships));
-            let total_players = 2;
-            let first_turn_idx = rand::rng().random_range(0..total_players);
-            self.current_turn = match first_turn_idx {
-                0 => self.p1.as_ref().unwrap().name.clone(),
-                _ => self.p2.as_ref().unwrap().name.clone(),
-            };
-            self.status = GameStatus::Progress;
-            return;
+        writer.send(Write::Events(self.id.clone(), events.clone()));
+        if self.core.rules.clock_seconds.is_some() {
+            writer.send(Write::Clocks(self.id.clone(), self.core.clocks()));
         }
+        for event in events.iter() {
+            if let GameEvent::GameOver(result) = event {
+                writer.send(Write::Result(FinishedGame {
+                    game_id: self.id.clone(),
+                    round: self.core.round,
+                    finished: unix_secs(SystemTime::now()),
+                    result: result.clone(),
+                }));
+            }
+        }
+        Ok(events)
     }
 }
 
@@ -171,170 +368,41 @@ impl Client {
     pub fn new(id: String, sender: Sender<WsEvent>) -> Self {
         Self { id, sender }
     }
-}
 
-#[derive(Debug)]
-pub struct Player {
-    pub name: String,
-    pub grid_state: Vec<Vec<CellType>>,
-    pub ship_health: HashMap<Point2d, Arc<Mutex<Ship>>>,
+    /// Delivery to a player who already left is dropped, disconnects are handled by `client_left`
+    pub async fn send(&self, event: WsEvent) {
+        let _ = self.sender.send(event).await;
+    }
 }
 
-impl Player {
-    pub fn new(name: String, ships: Vec<Ship>) -> Self {
-        let mut ship_health: HashMap<Point2d, Arc<Mutex<Ship>>> = HashMap::new();
-        for s in ships.into_iter() {
-            let arc = Arc::new(Mutex::new(Ship::new(s.coords.to_vec(), s.ship_type)));
-            for xy in &s.coords {
-                ship_health.insert(Point2d { x: xy.x, y: xy.y }, arc.clone());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::tests::classic_fleet;
+
+    /// Whether the first mover could be read off the id. The first mover is the top
+    /// bit of the rng's first word, so is the upper half of the id's first character.
+    #[test]
+    fn game_id_does_not_give_away_the_first_mover() {
+        const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+        let seeds = 2000;
+        let mut guessed = 0;
+        for seed in 0..seeds {
+            let mut game = Game::new(RuleSet::classic(), seed);
+            for player in ["a", "b"] {
+                let join = Action::Join {
+                    player: player.to_string(),
+                    ships: classic_fleet(),
+                };
+                game.core.apply(join).unwrap();
             }
-        }
-
-        Self {
-            name,
-            grid_state: {
-                let mut state = vec![vec![CellType::EmptyNoShip; 10]; 10];
-                for (point, ship) in ship_health.iter() {
-                    state[point.x][point.y] = CellType::HasShip
-                }
-                state
-            },
-            ship_health,
-        }
-    }
-
-    pub fn is_all_destroyed(&self) -> bool {
-        let mut all_destroyed = true;
-        for arc in self.ship_health.values() {
-            if arc.lock().unwrap().health > 0 {
-                all_destroyed = false;
-                break;
+            let first = game.id.as_bytes()[0];
+            let upper_half = CHARSET.iter().position(|c| *c == first).unwrap() >= 32;
+            if upper_half == (game.core.current_turn == "b") {
+                guessed += 1;
             }
         }
-
-        return all_destroyed;
-    }
-}
-
-#[derive(PartialEq, Debug)]
-pub enum GameFlow {
-    NextTurn,
-    GameOver,
-}
-
-#[derive(Debug, Default, Clone, PartialEq, Copy)]
-pub enum CellType {
-    #[default]
-    EmptyNoShip,
-    HasShip,
-    EmptyMissed, // miss
-    HasShipHit,
-}
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-pub struct Point2d {
-    pub x: usize,
-    pub y: usize,
-}
-
-impl Point2d {
-    pub fn new(x: usize, y: usize) -> Self {
-        Self { x, y }
-    }
-}
-
-#[derive(Clone, PartialEq, Debug, Copy)]
-pub enum ShipType {
-    OneDeck1,
-    OneDeck2,
-    OneDeck3,
-    OneDeck4,
-    TwoDeck1,
-    TwoDeck2,
-    TwoDeck3,
-    ThreeDeck1,
-    ThreeDeck2,
-    FourDeck1,
-}
-
-#[derive(Debug)]
-pub struct Ship {
-    pub coords: Vec<Point2d>,
-    pub ship_type: ShipType,
-    pub health: usize,
-}
-
-impl Ship {
-    pub fn new(coords: Vec<Point2d>, ship_type: ShipType) -> Self {
-        Self {
-            coords,
-            ship_type,
-            health: match ship_type {
-                ShipType::OneDeck1 => 1,
-                ShipType::OneDeck2 => 1,
-                ShipType::OneDeck3 => 1,
-                ShipType::OneDeck4 => 1,
-                ShipType::TwoDeck1 => 2,
-                ShipType::TwoDeck2 => 2,
-                ShipType::TwoDeck3 => 2,
-                ShipType::ThreeDeck1 => 3,
-                ShipType::ThreeDeck2 => 3,
-                ShipType::FourDeck1 => 4,
-            },
-        }
-    }
-
-    pub fn hit(&mut self) -> HashSet<Point2d> {
-        self.health -= 1;
-
-        let mut set = HashSet::new();
-        if !self.is_dead() {
-            return set;
-        }
-
-        for p in self.coords.iter() {
-            let (x, y) = (p.x, p.y);
-            let near_down = Point2d::new(if x + 1 > 9 { 9 } else { x + 1 }, y);
-            let near_up = Point2d::new(if x + 1 - 1 == 0 { 0 } else { x - 1 }, y);
-            let near_left = Point2d::new(x, if y + 1 - 1 == 0 { 0 } else { y - 1 });
-            let near_right = Point2d::new(x, if y + 1 > 9 { 9 } else { y + 1 });
-
-            let near_down_right = Point2d::new(
-                if x + 1 > 9 { 9 } else { x + 1 },
-                if y + 1 > 9 { 9 } else { y + 1 },
-            );
-            let near_down_left = Point2d::new(
-                if x + 1 > 9 { 9 } else { x + 1 },
-                if y + 1 - 1 == 0 { 0 } else { y - 1 },
-            );
-            let near_up_right = Point2d::new(
-                if x + 1 - 1 == 0 { 0 } else { x - 1 },
-                if y + 1 > 9 { 9 } else { y + 1 },
-            );
-            let near_up_left = Point2d::new(
-                if x + 1 - 1 == 0 { 0 } else { x - 1 },
-                if y + 1 - 1 == 0 { 0 } else { y - 1 },
-            );
-
-            set.insert(near_right);
-            set.insert(near_left);
-            set.insert(near_up);
-            set.insert(near_down);
-
-            set.insert(near_up_right);
-            set.insert(near_up_left);
-            set.insert(near_down_right);
-            set.insert(near_down_left);
-        }
-
-        for p in self.coords.iter() {
-            set.remove(p);
-        }
-
-        return set;
-    }
-
-    pub fn is_dead(&self) -> bool {
-        return self.health <= 0;
+        let rate = guessed as f64 / seeds as f64;
+        assert!((0.45..0.55).contains(&rate), "first mover guessed {}", rate);
     }
 }
@@ -0,0 +1,651 @@
+use crate::app_state::{Client, Wrapper};
+use crate::dto::{
+    ClientId, GameId, GameStatus, PlayerAction, RematchRequest, SalvoRequest, ShipsRaw,
+    TurnRequest, WsEvent,
+};
+use crate::engine::{Player, Point2d, RuleError};
+use crate::error::ServerError;
+use crate::game_engine;
+use crate::game_engine::grid_as_json_single;
+use crate::rules::{random_fleet, GameMode, RuleSet};
+use crate::storage::PlayerRecord;
+use rand::distr::{Alphanumeric, SampleString};
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Pause before the bot shoots so its moves can be followed on screen
+const THINK_TIME: Duration = Duration::from_millis(700);
+/// Mixed into the game seed for the bot's fleets, apart from the game's rng
+const FLEET_SALT: u64 = 0x5851_f42d_4c95_7f2d;
+
+/// How well the bot plays, picked in `PlayBotRq`
+#[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum BotLevel {
+    /// Hunt and target, three shots out of four at random
+    Easy,
+    /// The density search, with about a third of the shots at random
+    #[default]
+    Medium,
+    /// Always the most likely cell
+    Hard,
+}
+
+/// What the bot knows about a cell of the enemy grid
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Known {
+    Unknown,
+    Miss,
+    Hit,
+    Sunk,
+}
+
+impl BotLevel {
+    /// Level for a queued player: medium until they have a few games, then by win rate
+    pub fn matched(record: Option<&PlayerRecord>) -> BotLevel {
+        match record {
+            Some(r) if r.played >= 3 && r.wins * 3 >= r.played * 2 => BotLevel::Hard,
+            Some(r) if r.played >= 3 && r.wins * 3 <= r.played => BotLevel::Easy,
+            _ => BotLevel::Medium,
+        }
+    }
+}
+
+pub fn bot_id(rng: &mut impl Rng) -> ClientId {
+    format!("bot-{}", Alphanumeric.sample_string(rng, 8))
+}
+
+/// The bot's fleet for a round of the game. It needs the seed only, not the game,
+/// so it is drawn outside the state lock.
+pub fn fleet(rules: &RuleSet, seed: u64, round: u32) -> Result<ShipsRaw, RuleError> {
+    let mut rng = StdRng::seed_from_u64(seed ^ FLEET_SALT ^ round as u64);
+    random_fleet(rules, &mut rng)
+}
+
+/// Attaches a server side player to the game. It has no socket: its `Client` feeds a task
+/// that answers the same events a browser gets and moves through the regular turn path.
+pub fn spawn(wrapper: Wrapper, game_id: GameId, bot_id: ClientId, level: BotLevel) -> Client {
+    let (sender, mut receiver) = mpsc::channel(10);
+    let (inbox, mut events) = mpsc::unbounded_channel();
+
+    // the bot task sends to its own client while it plays, so the client channel is drained
+    // right away and never fills up waiting on the task
+    tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            if inbox.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let name = bot_id.clone();
+    tokio::spawn(async move {
+        while let Some(event) = events.recv().await {
+            match event {
+                WsEvent::StateRs(state)
+                    if state.status == GameStatus::Progress
+                        && state.action == Some(PlayerAction::Shoot) =>
+                {
+                    tokio::time::sleep(THINK_TIME).await;
+                    if let Err(e) = play_turn(wrapper.clone(), &game_id, &name, level).await {
+                        println!("Bot move rejected: {} {}", &game_id, e);
+                    }
+                }
+                // always up for another game
+                WsEvent::RematchRs {
+                    player_id, started, ..
+                } if !started && player_id != name => {
+                    if let Err(e) = accept_rematch(wrapper.clone(), &game_id, &name).await {
+                        println!("Bot rematch rejected: {} {}", &game_id, e);
+                    }
+                }
+                WsEvent::Disconnect => break,
+                _ => {}
+            }
+        }
+        println!("Bot left: {} {}", &game_id, &name);
+    });
+
+    Client::new(bot_id, sender)
+}
+
+async fn play_turn(
+    wrapper: Wrapper,
+    game_id: &str,
+    name: &str,
+    level: BotLevel,
+) -> Result<(), ServerError> {
+    let (mode, shots) = {
+        let state = &mut wrapper.shared.state.write().unwrap();
+        let Some(game) = state.games.get_mut(game_id) else {
+            return Ok(());
+        };
+        let game = &mut game.core;
+        if game.status != GameStatus::Progress || game.current_turn != name {
+            return Ok(());
+        }
+        let Some(enemy) = game.opponent_of(name) else {
+            return Ok(());
+        };
+
+        let count = match game.rules.mode {
+            GameMode::Classic => 1,
+            GameMode::Salvo => game.player(name).map_or(0, |p| p.alive_ships()),
+        };
+        let view = board_view(enemy);
+        let shots = pick_shots(&view, &game.rules, level, count, &mut game.rng);
+        (game.rules.mode, shots)
+    };
+
+    let Some(first) = shots.first().copied() else {
+        return Ok(());
+    };
+    match mode {
+        GameMode::Classic => {
+            let rq = TurnRequest {
+                game_id: game_id.to_string(),
+                username: name.to_string(),
+                x: first.x,
+                y: first.y,
+            };
+            game_engine::game_turn(wrapper.clone(), rq)?;
+        }
+        GameMode::Salvo => {
+            let rq = SalvoRequest {
+                game_id: game_id.to_string(),
+                username: name.to_string(),
+                shots: shots.iter().map(|p| (p.x, p.y)).collect(),
+            };
+            let WsEvent::SalvoRs { results } = game_engine::game_salvo(wrapper.clone(), rq)? else {
+                return Ok(());
+            };
+            let (me, opponent) = wrapper.get_clients(game_id)?;
+            opponent
+                .send(WsEvent::SalvoRs {
+                    results: results.clone(),
+                })
+                .await;
+            me.send(WsEvent::SalvoRs { results }).await;
+        }
+    }
+
+    game_engine::notify_players(wrapper, game_id).await;
+    Ok(())
+}
+
+async fn accept_rematch(wrapper: Wrapper, game_id: &str, name: &str) -> Result<(), ServerError> {
+    let (rules, seed, round) = {
+        let state = wrapper.shared.state.read().unwrap();
+        let Some(game) = state.games.get(game_id) else {
+            return Ok(());
+        };
+        (game.core.rules.clone(), game.core.seed, game.core.round)
+    };
+    let ships = fleet(&rules, seed, round + 1)?;
+
+    let rq = RematchRequest {
+        game_id: game_id.to_string(),
+        username: name.to_string(),
+        ships,
+    };
+    let response = game_engine::game_rematch(wrapper.clone(), rq)?;
+    let WsEvent::RematchRs { started, .. } = response else {
+        return Ok(());
+    };
+
+    let (me, opponent) = wrapper.get_clients(game_id)?;
+    me.send(response.clone()).await;
+    opponent.send(response).await;
+    if started {
+        game_engine::notify_players(wrapper, game_id).await;
+    }
+    Ok(())
+}
+
+/// The enemy grid exactly as a player sees it, plus which hit ships went down
+pub fn board_view(enemy: &Player) -> Vec<Vec<Known>> {
+    let grid = grid_as_json_single(enemy, true);
+    let mut view = vec![];
+    for (x, row) in grid.iter().enumerate() {
+        let mut known = vec![];
+        for (y, cell) in row.iter().enumerate() {
+            known.push(match cell.as_str() {
+                "_" | "~" => Known::Miss,
+                "x" => {
+                    let sunk = enemy.ship(Point2d::new(x, y)).is_some_and(|s| s.is_dead());
+                    if sunk {
+                        Known::Sunk
+                    } else {
+                        Known::Hit
+                    }
+                }
+                _ => Known::Unknown,
+            });
+        }
+        view.push(known);
+    }
+    view
+}
+
+/// Up to `count` distinct shots picked the way the level plays
+pub fn pick_shots(
+    view: &[Vec<Known>],
+    rules: &RuleSet,
+    level: BotLevel,
+    count: usize,
+    rng: &mut impl Rng,
+) -> Vec<Point2d> {
+    let mut taken = HashSet::new();
+    let mut shots = vec![];
+    while shots.len() < count {
+        let pick = match level {
+            BotLevel::Easy if rng.random_bool(0.75) => random_open(view, &taken, rng),
+            BotLevel::Easy => hunt_target(view, rules, &taken, rng),
+            BotLevel::Medium if rng.random_bool(0.3) => random_open(view, &taken, rng),
+            BotLevel::Medium | BotLevel::Hard => most_likely(view, rules, &taken, rng)
+                .or_else(|| hunt_target(view, rules, &taken, rng)),
+        };
+        let Some(p) = pick else {
+            break;
+        };
+        taken.insert(p);
+        shots.push(p);
+    }
+    shots
+}
+
+/// Target mode finishes a wounded ship along its axis, hunt mode fires on a checkerboard
+/// away from sunk ships since ships never touch
+pub fn hunt_target(
+    view: &[Vec<Known>],
+    rules: &RuleSet,
+    taken: &HashSet<Point2d>,
+    rng: &mut impl Rng,
+) -> Option<Point2d> {
+    let at = |p: Point2d| view[p.x][p.y];
+    let open = |p: Point2d| at(p) == Known::Unknown && !taken.contains(&p);
+
+    let mut hits = vec![];
+    let mut unknown = vec![];
+    for (x, row) in view.iter().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            let p = Point2d::new(x, y);
+            match cell {
+                Known::Hit => hits.push(p),
+                Known::Unknown if !taken.contains(&p) => unknown.push(p),
+                _ => {}
+            }
+        }
+    }
+
+    if !hits.is_empty() {
+        let mut along_axis = vec![];
+        let mut around = vec![];
+        for &hit in hits.iter() {
+            for (dx, dy) in [(0, 1), (0, -1), (1, 0), (-1, 0)] {
+                let Some(next) = step(rules, hit, dx, dy) else {
+                    continue;
+                };
+                // a hit behind means the ship lies on this axis, keep walking to its end
+                let behind = step(rules, hit, -dx, -dy).is_some_and(|b| at(b) == Known::Hit);
+                if open(next) {
+                    if behind {
+                        along_axis.push(next);
+                    } else {
+                        around.push(next);
+                    }
+                }
+            }
+        }
+        let candidates = if along_axis.is_empty() {
+            around
+        } else {
+            along_axis
+        };
+        if let Some(p) = candidates.choose(rng) {
+            return Some(*p);
+        }
+    }
+
+    // cells next to a sunk ship or diagonal to a hit can't hold a ship
+    let free: Vec<Point2d> = unknown
+        .into_iter()
+        .filter(|&p| {
+            rules
+                .neighbours(p)
+                .into_iter()
+                .all(|n| at(n) != Known::Sunk && !(at(n) == Known::Hit && n.x != p.x && n.y != p.y))
+        })
+        .collect();
+    let parity: Vec<Point2d> = free
+        .iter()
+        .copied()
+        .filter(|p| (p.x + p.y) % 2 == 0)
+        .collect();
+
+    parity
+        .choose(rng)
+        .or_else(|| free.choose(rng))
+        .copied()
+        // nothing sensible left, any open cell
+        .or_else(|| random_open(view, taken, rng))
+}
+
+/// Any cell that was not shot yet
+fn random_open(
+    view: &[Vec<Known>],
+    taken: &HashSet<Point2d>,
+    rng: &mut impl Rng,
+) -> Option<Point2d> {
+    let mut cells = vec![];
+    for (x, row) in view.iter().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            let p = Point2d::new(x, y);
+            if *cell == Known::Unknown && !taken.contains(&p) {
+                cells.push(p);
+            }
+        }
+    }
+    cells.choose(rng).copied()
+}
+
+/// Shot at the cell covered by the most placements of the remaining fleet that still fit the
+/// visible grid. Placements through unresolved hits weigh much more, they finish wounded ships.
+pub fn most_likely(
+    view: &[Vec<Known>],
+    rules: &RuleSet,
+    taken: &HashSet<Point2d>,
+    rng: &mut impl Rng,
+) -> Option<Point2d> {
+    let density = density(view, rules);
+
+    let mut best = vec![];
+    let mut best_score = 0;
+    for (x, row) in density.iter().enumerate() {
+        for (y, &score) in row.iter().enumerate() {
+            let p = Point2d::new(x, y);
+            if view[x][y] != Known::Unknown || taken.contains(&p) || score == 0 {
+                continue;
+            }
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {
+                best.push(p);
+            }
+        }
+    }
+    best.choose(rng).copied()
+}
+
+/// Extra weight of a placement for every unresolved hit it passes through
+const HIT_WEIGHT: u32 = 50;
+
+/// For every cell, the weighted number of remaining ship placements covering it
+pub fn density(view: &[Vec<Known>], rules: &RuleSet) -> Vec<Vec<u32>> {
+    let at = |p: Point2d| view[p.x][p.y];
+    let mut density: Vec<Vec<u32>> = view.iter().map(|row| vec![0; row.len()]).collect();
+
+    for length in remaining_fleet(view, rules) {
+        let directions: &[(isize, isize)] = if length == 1 {
+            &[(0, 1)]
+        } else {
+            &[(0, 1), (1, 0)]
+        };
+        for x in 0..rules.height {
+            for y in 0..rules.width {
+                for &(dx, dy) in directions {
+                    let Some(cells) = placement(rules, Point2d::new(x, y), dx, dy, length) else {
+                        continue;
+                    };
+                    if cells
+                        .iter()
+                        .any(|&p| matches!(at(p), Known::Miss | Known::Sunk))
+                    {
+                        continue;
+                    }
+                    // ships never touch, a hit next to the placement would be another ship
+                    let touches = cells.iter().any(|&p| {
+                        rules.neighbours(p).into_iter().any(|n| {
+                            !cells.contains(&n) && matches!(at(n), Known::Hit | Known::Sunk)
+                        })
+                    });
+                    if touches {
+                        continue;
+                    }
+
+                    let hits = cells.iter().filter(|&&p| at(p) == Known::Hit).count() as u32;
+                    let weight = 1 + hits * HIT_WEIGHT;
+                    for p in cells {
+                        if at(p) == Known::Unknown {
+                            density[p.x][p.y] += weight;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    density
+}
+
+/// Lengths of the ships still afloat, sunk ones are told apart by their size
+fn remaining_fleet(view: &[Vec<Known>], rules: &RuleSet) -> Vec<usize> {
+    let mut lengths: Vec<usize> = rules
+        .fleet
+        .iter()
+        .flat_map(|e| std::iter::repeat_n(e.length, e.count))
+        .collect();
+
+    let mut seen = HashSet::new();
+    for (x, row) in view.iter().enumerate() {
+        for (y, cell) in row.iter().enumerate() {
+            let start = Point2d::new(x, y);
+            if *cell != Known::Sunk || seen.contains(&start) {
+                continue;
+            }
+            // sunk ships never touch each other, so one connected group is one ship
+            let mut stack = vec![start];
+            let mut size = 0;
+            seen.insert(start);
+            while let Some(p) = stack.pop() {
+                size += 1;
+                for n in rules.neighbours(p) {
+                    if view[n.x][n.y] == Known::Sunk && seen.insert(n) {
+                        stack.push(n);
+                    }
+                }
+            }
+            if let Some(idx) = lengths.iter().position(|&l| l == size) {
+                lengths.remove(idx);
+            }
+        }
+    }
+    lengths
+}
+
+fn placement(
+    rules: &RuleSet,
+    start: Point2d,
+    dx: isize,
+    dy: isize,
+    length: usize,
+) -> Option<Vec<Point2d>> {
+    let mut cells = vec![start];
+    for _ in 1..length {
+        cells.push(step(rules, *cells.last().unwrap(), dx, dy)?);
+    }
+    Some(cells)
+}
+
+fn step(rules: &RuleSet, p: Point2d, dx: isize, dy: isize) -> Option<Point2d> {
+    let x = p.x.checked_add_signed(dx)?;
+    let y = p.y.checked_add_signed(dy)?;
+    let next = Point2d::new(x, y);
+    rules.contains(next).then_some(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::FleetEntry;
+    use rand::rngs::StdRng;
+
+    fn blank(rules: &RuleSet) -> Vec<Vec<Known>> {
+        vec![vec![Known::Unknown; rules.width]; rules.height]
+    }
+
+    fn adjacent(a: Point2d, b: Point2d) -> bool {
+        a.x.abs_diff(b.x) + a.y.abs_diff(b.y) == 1
+    }
+
+    #[test]
+    fn target_mode_shoots_next_to_a_hit() {
+        let rules = RuleSet::classic();
+        let mut view = blank(&rules);
+        view[4][4] = Known::Hit;
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let shot = hunt_target(&view, &rules, &HashSet::new(), &mut rng).unwrap();
+            assert!(adjacent(shot, Point2d::new(4, 4)), "{:?}", shot);
+        }
+    }
+
+    #[test]
+    fn target_mode_follows_the_axis_of_two_hits() {
+        let rules = RuleSet::classic();
+        let mut view = blank(&rules);
+        view[4][4] = Known::Hit;
+        view[4][5] = Known::Hit;
+        view[4][6] = Known::Miss;
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let shot = hunt_target(&view, &rules, &HashSet::new(), &mut rng);
+            assert_eq!(shot, Some(Point2d::new(4, 3)));
+        }
+    }
+
+    #[test]
+    fn hunt_mode_keeps_off_sunk_ships_and_to_the_checkerboard() {
+        let rules = RuleSet::classic();
+        let mut view = blank(&rules);
+        view[0][0] = Known::Sunk;
+        for seed in 0..50 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let shot = hunt_target(&view, &rules, &HashSet::new(), &mut rng).unwrap();
+            assert_eq!((shot.x + shot.y) % 2, 0, "{:?}", shot);
+            assert!(shot.x > 1 || shot.y > 1, "{:?}", shot);
+        }
+    }
+
+    #[test]
+    fn hard_shoots_the_density_maximum() {
+        let rules = RuleSet::classic();
+        let mut view = blank(&rules);
+        view[2][3] = Known::Miss;
+        view[7][7] = Known::Miss;
+        let density = density(&view, &rules);
+        let max = *density.iter().flatten().max().unwrap();
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let shots = pick_shots(&view, &rules, BotLevel::Hard, 1, &mut rng);
+            let p = shots[0];
+            assert_eq!(density[p.x][p.y], max, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn density_leans_on_an_open_hit() {
+        let rules = RuleSet::classic();
+        let mut view = blank(&rules);
+        view[5][5] = Known::Hit;
+        let density = density(&view, &rules);
+        let max = *density.iter().flatten().max().unwrap();
+        let best: Vec<Point2d> = (0..10)
+            .flat_map(|x| (0..10).map(move |y| Point2d::new(x, y)))
+            .filter(|p| density[p.x][p.y] == max)
+            .collect();
+        assert!(best.iter().all(|&p| adjacent(p, Point2d::new(5, 5))));
+        // diagonal to a hit is never a ship
+        assert_eq!(density[4][4], 0);
+    }
+
+    #[test]
+    fn sunk_ships_leave_the_fleet() {
+        let rules = RuleSet::classic();
+        let mut view = blank(&rules);
+        view[0][..4].fill(Known::Sunk);
+        view[9][9] = Known::Sunk;
+        let mut left = remaining_fleet(&view, &rules);
+        left.sort();
+        assert_eq!(left, vec![1, 1, 1, 2, 2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn density_counts_only_ships_still_afloat() {
+        let mut rules = RuleSet::classic();
+        rules.fleet = vec![
+            FleetEntry {
+                length: 4,
+                count: 1,
+            },
+            FleetEntry {
+                length: 1,
+                count: 1,
+            },
+        ];
+        let mut view = blank(&rules);
+        view[0][..4].fill(Known::Sunk);
+        // only the single-deck ship is left, it fits once into every free cell
+        let density = density(&view, &rules);
+        assert_eq!(density[5][5], 1);
+        assert!(density.iter().flatten().all(|&d| d <= 1));
+        assert_eq!(density[1][1], 0);
+    }
+
+    #[test]
+    fn levels_differ_in_how_often_they_ignore_a_hit() {
+        let rules = RuleSet::classic();
+        let mut view = blank(&rules);
+        view[4][4] = Known::Hit;
+        let near = |level: BotLevel| {
+            (0..200)
+                .filter(|&seed| {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let shot = pick_shots(&view, &rules, level, 1, &mut rng)[0];
+                    adjacent(shot, Point2d::new(4, 4))
+                })
+                .count()
+        };
+        let (easy, medium, hard) = (
+            near(BotLevel::Easy),
+            near(BotLevel::Medium),
+            near(BotLevel::Hard),
+        );
+        assert_eq!(hard, 200);
+        assert!(
+            medium < hard && easy < medium,
+            "{} {} {}",
+            easy,
+            medium,
+            hard
+        );
+    }
+
+    #[test]
+    fn salvo_picks_distinct_cells() {
+        let rules = RuleSet::classic();
+        let view = blank(&rules);
+        for level in [BotLevel::Easy, BotLevel::Medium, BotLevel::Hard] {
+            let mut rng = StdRng::seed_from_u64(3);
+            let shots = pick_shots(&view, &rules, level, 7, &mut rng);
+            let unique: HashSet<Point2d> = shots.iter().copied().collect();
+            assert_eq!((shots.len(), unique.len()), (7, 7), "{:?}", level);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Longest chat message in characters
+pub const MAX_CHAT_LEN: usize = 200;
+/// Messages a single connection may send per `CHAT_WINDOW`
+pub const CHAT_BURST: usize = 5;
+pub const CHAT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Sliding window rate limit for the chat of one connection
+#[derive(Debug, Default)]
+pub struct ChatLimiter {
+    sent: VecDeque<Instant>,
+}
+
+impl ChatLimiter {
+    /// Records a message sent at `now`, false if the connection is over the limit
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while self
+            .sent
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= CHAT_WINDOW)
+        {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= CHAT_BURST {
+            return false;
+        }
+
+        self.sent.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_is_allowed_then_limited() {
+        let mut limiter = ChatLimiter::default();
+        let start = Instant::now();
+        for _ in 0..CHAT_BURST {
+            assert!(limiter.allow(start));
+        }
+        assert!(!limiter.allow(start));
+        assert!(!limiter.allow(start + CHAT_WINDOW - Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn messages_leave_the_window_after_it_passed() {
+        let mut limiter = ChatLimiter::default();
+        let start = Instant::now();
+        for i in 0..CHAT_BURST {
+            assert!(limiter.allow(start + Duration::from_secs(i as u64)));
+        }
+
+        // the first message is exactly one window old, it no longer counts
+        assert!(limiter.allow(start + CHAT_WINDOW));
+        assert!(!limiter.allow(start + CHAT_WINDOW));
+        assert!(limiter.allow(start + CHAT_WINDOW + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn rejected_message_does_not_count() {
+        let mut limiter = ChatLimiter::default();
+        let start = Instant::now();
+        for _ in 0..CHAT_BURST {
+            limiter.allow(start);
+        }
+        for _ in 0..10 {
+            assert!(!limiter.allow(start + Duration::from_secs(1)));
+        }
+        assert!(limiter.allow(start + CHAT_WINDOW));
+    }
+}
@@ -0,0 +1,108 @@
+use crate::dto::ClientId;
+use rand::RngCore;
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+/// Server wide settings, read from the environment on boot
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// How long a game waits for a player who lost the connection
+    pub disconnect_grace: Duration,
+    /// The same for games brought back after a restart, players need time to notice and
+    /// reload the page, the client resumes its seat on load
+    pub restore_grace: Duration,
+    /// How long a queued player waits for a human before the bot takes the seat
+    pub queue_bot_wait: Duration,
+    /// Registered external bots, token to bot name, from `BOT_TOKENS=name:token,...`
+    pub bot_tokens: HashMap<String, ClientId>,
+    /// Guards starting tournaments, they are disabled without it
+    pub admin_token: Option<String>,
+    /// Key for signing resume tokens, random per boot unless `RESUME_SECRET` is set
+    pub resume_secret: Vec<u8>,
+    /// How long a resume token stays valid, a connect hands out a fresh one
+    pub resume_ttl: Duration,
+    /// Seeds the game seeds from `GAME_SEED`, random per boot otherwise
+    pub game_seed: Option<u64>,
+    /// SQLite file from `DATABASE`, running games survive a restart. Without it they
+    /// live in memory only. Set `RESUME_SECRET` too, or players can't take their seats back.
+    pub database: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            disconnect_grace: Duration::from_secs(30),
+            restore_grace: Duration::from_secs(300),
+            queue_bot_wait: Duration::from_secs(30),
+            bot_tokens: HashMap::new(),
+            admin_token: None,
+            resume_secret: random_secret(),
+            resume_ttl: Duration::from_secs(24 * 3600),
+            game_seed: None,
+            database: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        Self {
+            disconnect_grace: env_secs("DISCONNECT_GRACE_SECS").unwrap_or(default.disconnect_grace),
+            restore_grace: env_secs("RESTORE_GRACE_SECS").unwrap_or(default.restore_grace),
+            queue_bot_wait: env_secs("QUEUE_BOT_WAIT_SECS").unwrap_or(default.queue_bot_wait),
+            bot_tokens: env::var("BOT_TOKENS")
+                .map(|v| parse_bot_tokens(&v))
+                .unwrap_or_default(),
+            admin_token: env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            resume_secret: env::var("RESUME_SECRET")
+                .map(String::into_bytes)
+                .unwrap_or(default.resume_secret),
+            resume_ttl: env_secs("RESUME_TTL_SECS").unwrap_or(default.resume_ttl),
+            game_seed: env_seed(),
+            database: env::var("DATABASE").ok().filter(|p| !p.is_empty()),
+        }
+    }
+}
+
+fn env_secs(name: &str) -> Option<Duration> {
+    let value = env::var(name).ok()?;
+    match value.parse() {
+        Ok(secs) => Some(Duration::from_secs(secs)),
+        Err(_) => {
+            println!("Ignoring {}={}, expected seconds", name, value);
+            None
+        }
+    }
+}
+
+fn env_seed() -> Option<u64> {
+    let value = env::var("GAME_SEED").ok()?;
+    match value.parse() {
+        Ok(seed) => Some(seed),
+        Err(_) => {
+            println!("Ignoring GAME_SEED={}, expected a number", value);
+            None
+        }
+    }
+}
+
+fn parse_bot_tokens(value: &str) -> HashMap<String, ClientId> {
+    let mut tokens = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match entry.split_once(':') {
+            Some((name, token)) if !name.is_empty() && !token.is_empty() => {
+                tokens.insert(token.to_string(), name.to_string());
+            }
+            _ => println!("Ignoring bot token entry {}, expected name:token", entry),
+        }
+    }
+    tokens
+}
+
+fn random_secret() -> Vec<u8> {
+    let mut secret = vec![0; 32];
+    rand::rng().fill_bytes(&mut secret);
+    secret
+}
@@ -1,3 +1,5 @@
+use crate::bot::BotLevel;
+use crate::rules::RuleSet;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -6,25 +8,96 @@ pub type ClientId = String;
 pub type ShipsRaw = Vec<Vec<(usize, usize)>>;
 pub type Grid2D = Vec<Vec<String>>;
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase", rename_all_fields = "camelCase")]
 pub enum WsEvent {
-    ConnectRq { player_id: ClientId },
-    ConnectRs { player_id: ClientId },
+    ConnectRq {
+        player_id: ClientId,
+        #[serde(default)]
+        resume_token: Option<String>,
+        /// Identifies an external bot, see `BOT_TOKENS`
+        #[serde(default)]
+        bot_token: Option<String>,
+    },
+    ConnectRs {
+        player_id: ClientId,
+        resume_token: String,
+        game_id: Option<GameId>,
+    },
     CreateGameRq(CreateGameRequest),
-    CreateGameRs { game_id: GameId, status: GameStatus },
-    GameStart { game_id: GameId },
-    GameOver,
+    CreateGameRs {
+        game_id: GameId,
+        status: GameStatus,
+    },
+    GameStart {
+        game_id: GameId,
+    },
+    OpponentDisconnected {
+        player_id: ClientId,
+        reconnect_ms: Option<u64>,
+    },
+    OpponentReconnected {
+        player_id: ClientId,
+    },
+    GameOver(GameResult),
+    ResignRq(ResignRequest),
+    SeriesOver(SeriesScore),
+    RematchRq(RematchRequest),
+    RematchRs {
+        game_id: GameId,
+        player_id: ClientId,
+        round: u32,
+        started: bool,
+    },
+    PlayBotRq(PlayBotRequest),
     QueueRq(QueueRequest),
-    QueueRs { player_id: ClientId },
+    QueueRs {
+        player_id: ClientId,
+    },
+    /// Sent by the matchmaker before `GameStart`, tells whether the opponent is a bot
+    MatchedRs {
+        game_id: GameId,
+        opponent_id: ClientId,
+        bot: bool,
+    },
+    /// Asks a tournament bot for its fleet, a missing `FleetRs` gets a random one
+    FleetRq {
+        rules: RuleSet,
+        seconds: u64,
+    },
+    FleetRs {
+        ships: ShipsRaw,
+    },
     JoinRq(JoinGameRequest),
     JoinRs(GridResponse, String),
     TurnRq(TurnRequest),
     TurnRs(GridDTO),
+    SalvoRq(SalvoRequest),
+    SalvoRs {
+        results: Vec<ShotOutcome>,
+    },
     StateRq(StateRequest),
     StateRs(GridResponse),
-    BadRequestRs(String),
-    ServerAbort,
+    SpectateRq(SpectateRequest),
+    SpectateRs(GridResponse),
+    ChatRq(ChatRequest),
+    ChatMsg {
+        game_id: GameId,
+        sender: ClientId,
+        text: String,
+        /// unix time in ms
+        sent_at: u64,
+    },
+    /// Rejected request, see `ServerError::code` for the codes
+    BadRequestRs {
+        code: String,
+        message: String,
+    },
+    /// The server failed, not the client
+    ServerAbort {
+        code: String,
+        message: String,
+    },
     Disconnect,
     Debug(String),
 }
@@ -37,6 +110,36 @@ pub enum GameStatus {
     Progress,
 }
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum GameOverReason {
+    AllShipsSunk,
+    Resignation,
+    TurnTimeout,
+    FlagFall,
+    Disconnect,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GameResult {
+    pub winner: ClientId,
+    pub loser: ClientId,
+    pub reason: GameOverReason,
+    /// shots fired by each player, repeated shots not counted
+    pub shots: HashMap<ClientId, usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SeriesScore {
+    pub best_of: u32,
+    pub round: u32,
+    /// rounds won by each player
+    pub score: HashMap<ClientId, u32>,
+    pub winner: Option<ClientId>,
+}
+
 #[derive(Debug, Copy, Serialize, Deserialize, Clone, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum PlayerAction {
@@ -44,20 +147,46 @@ pub enum PlayerAction {
     Wait,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum ShotResult {
+    Miss,
+    Hit,
+    Sunk,
+    Repeat,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ShotOutcome {
+    pub x: usize,
+    pub y: usize,
+    pub result: ShotResult,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct GridDTO {
     pub me: Grid2D,
     pub enemy: Grid2D,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct GridResponse {
     pub me: Option<Grid2D>,
     pub enemy: Option<Grid2D>,
     pub status: GameStatus,
     pub action: Option<PlayerAction>,
+    /// shots the turning player has this turn, salvo games only
+    pub shots: Option<usize>,
+    /// time left for the current move, timed games only
+    pub time_left_ms: Option<u64>,
+    /// chess clock of each player in ms, empty for games without a clock
+    pub clocks: HashMap<String, u64>,
+    pub result: Option<GameResult>,
+    /// best-of-N games only
+    pub series: Option<SeriesScore>,
     pub grid: HashMap<String, Grid2D>,
 }
 
@@ -68,12 +197,17 @@ impl GridResponse {
             enemy: None,
             status,
             action,
+            shots: None,
+            time_left_ms: None,
+            clocks: HashMap::new(),
+            result: None,
+            series: None,
             grid: HashMap::new(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct TurnRequest {
     pub game_id: String,
@@ -82,7 +216,30 @@ pub struct TurnRequest {
     pub y: usize,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SalvoRequest {
+    pub game_id: String,
+    pub username: String,
+    pub shots: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResignRequest {
+    pub game_id: String,
+    pub username: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RematchRequest {
+    pub game_id: String,
+    pub username: String,
+    pub ships: ShipsRaw,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct JoinGameRequest {
     pub game_id: String,
@@ -90,21 +247,36 @@ pub struct JoinGameRequest {
     pub ships: ShipsRaw,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct QueueRequest {
     pub username: ClientId,
     pub ships: ShipsRaw,
+    #[serde(default)]
+    pub rules: RuleSet,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayBotRequest {
+    pub username: ClientId,
+    pub ships: ShipsRaw,
+    #[serde(default)]
+    pub rules: RuleSet,
+    #[serde(default)]
+    pub level: BotLevel,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateGameRequest {
     pub username: ClientId,
     pub ships: ShipsRaw,
+    #[serde(default)]
+    pub rules: RuleSet,
 }
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct StateRequest {
     pub game_id: String,
@@ -116,3 +288,17 @@ impl StateRequest {
         Self { game_id, username }
     }
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SpectateRequest {
+    pub game_id: GameId,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatRequest {
+    pub game_id: GameId,
+    pub username: ClientId,
+    pub text: String,
+}
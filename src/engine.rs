@@ -0,0 +1,1289 @@
+//! Rules of a single game as a plain state machine: no runtime, sockets or locks.
+//! The server keeps one per `Game` and drives it with `Action`s.
+
+use crate::dto::{
+    ClientId, GameOverReason, GameResult, GameStatus, SeriesScore, ShipsRaw, ShotOutcome,
+    ShotResult,
+};
+use crate::rules::{validate_fleet, GameMode, PlacementError, RuleSet, TimeoutPolicy, TurnPolicy};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::time::{Duration, Instant};
+use GameStatus::{GameOver, Progress, WaitingPlayers};
+
+/// Everything that can happen to a game, by a player or by the server on the rules' behalf
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Join {
+        player: ClientId,
+        ships: ShipsRaw,
+    },
+    Shoot {
+        player: ClientId,
+        at: Point2d,
+    },
+    Salvo {
+        player: ClientId,
+        shots: Vec<Point2d>,
+    },
+    Resign {
+        player: ClientId,
+    },
+    Rematch {
+        player: ClientId,
+        ships: ShipsRaw,
+    },
+    /// Next round of an undecided series, both players keep their fleets
+    NextRound,
+    /// Flag fall or the turn timeout policy, once a deadline has passed
+    Timeout,
+    /// The player is gone for good, an unfinished game is lost
+    Abandon {
+        player: ClientId,
+    },
+    /// Loss decided off the board, e.g. a timeout read back from a replay
+    Forfeit {
+        player: ClientId,
+        reason: GameOverReason,
+    },
+}
+
+/// What an applied action changed, in order
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum GameEvent {
+    /// Fleet the player starts the round with, before `Joined` or `Started`
+    Placed {
+        player: ClientId,
+        ships: ShipsRaw,
+    },
+    Joined {
+        player: ClientId,
+    },
+    /// Both seats are taken or a new round began
+    Started {
+        round: u32,
+        first: ClientId,
+    },
+    Shot {
+        player: ClientId,
+        outcome: ShotOutcome,
+    },
+    TurnPassed {
+        to: ClientId,
+    },
+    RematchOffered {
+        player: ClientId,
+    },
+    GameOver(GameResult),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleError {
+    Placement(PlacementError),
+    GameFull,
+    AlreadyJoined,
+    NotAPlayer,
+    NotInProgress,
+    NotOver,
+    NotYourTurn,
+    OutOfBounds,
+    /// The move does not fit the mode the game is played in
+    WrongMode(GameMode),
+    SalvoSize {
+        allowed: usize,
+    },
+    DuplicateShot,
+    /// `random_fleet` found no room, the rules were not validated
+    NoRandomFleet,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::Placement(e) => write!(f, "{}", e),
+            RuleError::GameFull => write!(f, "Game is full"),
+            RuleError::AlreadyJoined => write!(f, "Already in this game"),
+            RuleError::NotAPlayer => write!(f, "Not a player of this game"),
+            RuleError::NotInProgress => write!(f, "Game is not in progress"),
+            RuleError::NotOver => write!(f, "Game is not over yet"),
+            RuleError::NotYourTurn => write!(f, "Not your turn"),
+            RuleError::OutOfBounds => write!(f, "Incorrect coordinates"),
+            RuleError::WrongMode(GameMode::Salvo) => write!(f, "This game is played in salvos"),
+            RuleError::WrongMode(GameMode::Classic) => {
+                write!(f, "Salvo is not enabled in this game")
+            }
+            RuleError::SalvoSize { allowed } => {
+                write!(f, "Salvo must have 1 to {} shots", allowed)
+            }
+            RuleError::DuplicateShot => write!(f, "Duplicate shot in salvo"),
+            RuleError::NoRandomFleet => write!(f, "No room for a random fleet"),
+        }
+    }
+}
+
+impl From<PlacementError> for RuleError {
+    fn from(e: PlacementError) -> Self {
+        RuleError::Placement(e)
+    }
+}
+
+#[derive(Debug)]
+pub struct GameCore {
+    pub p1: Option<Player>,
+    pub p2: Option<Player>,
+    pub current_turn: String,
+    pub status: GameStatus,
+    pub rules: RuleSet,
+    /// Set while a turn timer runs
+    pub turn_deadline: Option<Instant>,
+    /// When the running chess clock was last charged
+    pub clock_started: Option<Instant>,
+    pub result: Option<GameResult>,
+    /// Starts at 1, increased by every rematch
+    pub round: u32,
+    /// Fleets of players who asked for a rematch after game over
+    pub rematch_offers: HashMap<ClientId, ShipsRaw>,
+    /// Rounds won by each player, decides a best-of-N series
+    pub score: HashMap<ClientId, u32>,
+    /// Moves first in round one, drawn from the rng when unset
+    pub first: Option<ClientId>,
+    /// Seeds `rng`, the seed and the actions replay the game exactly
+    pub seed: u64,
+    /// Every random choice in the game, its bots included, draws from here
+    pub rng: StdRng,
+    /// Every event so far in order, `restore` rebuilds the game from it
+    pub log: Vec<GameEvent>,
+}
+
+impl GameCore {
+    pub fn new(rules: RuleSet, seed: u64) -> Self {
+        Self {
+            rules,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+            log: vec![],
+            turn_deadline: None,
+            clock_started: None,
+            result: None,
+            round: 1,
+            rematch_offers: HashMap::new(),
+            score: HashMap::new(),
+            first: None,
+            p1: None,
+            p2: None,
+            current_turn: String::new(),
+            status: WaitingPlayers,
+        }
+    }
+
+    /// Lets `player` move first in round one instead of a random pick
+    pub fn with_first(mut self, player: &str) -> Self {
+        self.first = Some(player.to_string());
+        self
+    }
+
+    /// Puts the chess clocks back to the time the players had left, see `clocks`
+    pub fn with_clocks(mut self, left: &HashMap<ClientId, u64>) -> Self {
+        for (name, ms) in left {
+            if let Some(p) = self.player_mut(name) {
+                p.time_left = p.time_left.map(|_| Duration::from_millis(*ms));
+            }
+        }
+        self
+    }
+
+    /// Chess clock of each player in ms, as charged at the last move
+    pub fn clocks(&self) -> HashMap<ClientId, u64> {
+        [self.p1.as_ref(), self.p2.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|p| Some((p.name.clone(), p.time_left?.as_millis() as u64)))
+            .collect()
+    }
+
+    /// Validates and applies the action, a rejected action leaves the game untouched
+    pub fn apply(&mut self, action: Action) -> Result<Vec<GameEvent>, RuleError> {
+        let events = self.run(action)?;
+        self.log.extend(events.iter().cloned());
+        Ok(events)
+    }
+
+    /// The game as it was after its first `shots` shots, up to the next shot.
+    /// 0 is the position before anyone fired.
+    pub fn after_shots(&self, shots: usize) -> GameCore {
+        let upto = self
+            .log
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| matches!(e, GameEvent::Shot { .. }))
+            .nth(shots)
+            .map_or(self.log.len(), |(idx, _)| idx);
+        GameCore::restore(self.rules.clone(), self.seed, &self.log[..upto])
+    }
+
+    /// Rebuilds a game from its log. Chess clocks are not logged, see `with_clocks`,
+    /// pending rematch offers are lost and the rng starts again from the seed.
+    /// Timers run again from now.
+    pub fn restore(rules: RuleSet, seed: u64, log: &[GameEvent]) -> GameCore {
+        let mut game = GameCore::new(rules, seed);
+        for event in log {
+            game.redo(event);
+        }
+        game.reset_turn_clock();
+        game.charge_clock();
+        game
+    }
+
+    fn run(&mut self, action: Action) -> Result<Vec<GameEvent>, RuleError> {
+        match action {
+            Action::Join { player, ships } => self.join(player, ships),
+            Action::Shoot { player, at } => self.shoot(player, at),
+            Action::Salvo { player, shots } => self.salvo(player, shots),
+            Action::Resign { player } => self.run(Action::Forfeit {
+                player,
+                reason: GameOverReason::Resignation,
+            }),
+            Action::Forfeit { player, reason } => {
+                if self.player(&player).is_none() {
+                    return Err(RuleError::NotAPlayer);
+                }
+                if self.status != Progress {
+                    return Err(RuleError::NotInProgress);
+                }
+                Ok(vec![self.finish(&player, reason)])
+            }
+            Action::Rematch { player, ships } => self.offer_rematch(player, ships),
+            Action::NextRound => Ok(self.next_round()),
+            Action::Timeout => Ok(self.timeout()),
+            Action::Abandon { player } => {
+                if self.player(&player).is_none() {
+                    return Err(RuleError::NotAPlayer);
+                }
+                if self.status != Progress {
+                    return Ok(vec![]);
+                }
+                Ok(vec![self.finish(&player, GameOverReason::Disconnect)])
+            }
+        }
+    }
+
+    fn join(&mut self, name: String, ships: ShipsRaw) -> Result<Vec<GameEvent>, RuleError> {
+        if self.player(&name).is_some() {
+            return Err(RuleError::AlreadyJoined);
+        }
+        if self.p2.is_some() {
+            return Err(RuleError::GameFull);
+        }
+        validate_fleet(&ships, &self.rules)?;
+        let mut events = vec![
+            GameEvent::Placed {
+                player: name.clone(),
+                ships: ships.clone(),
+            },
+            GameEvent::Joined {
+                player: name.clone(),
+            },
+        ];
+        let player = Player::new(name, ships_from_raw(ships), &self.rules);
+
+        if self.p1.is_none() {
+            self.p1 = Some(player);
+            return Ok(events);
+        }
+
+        self.p2 = Some(player);
+        let first = self.first.clone().filter(|f| self.player(f).is_some());
+        self.current_turn = match first {
+            Some(first) => first,
+            None => {
+                let total_players = 2;
+                let first_turn_idx = self.rng.random_range(0..total_players);
+                match first_turn_idx {
+                    0 => self.p1.as_ref().unwrap().name.clone(),
+                    _ => self.p2.as_ref().unwrap().name.clone(),
+                }
+            }
+        };
+        self.status = Progress;
+        self.reset_turn_clock();
+        self.charge_clock();
+        events.push(GameEvent::Started {
+            round: self.round,
+            first: self.current_turn.clone(),
+        });
+
+        Ok(events)
+    }
+
+    fn shoot(&mut self, requester: String, hit: Point2d) -> Result<Vec<GameEvent>, RuleError> {
+        if !self.rules.contains(hit) {
+            return Err(RuleError::OutOfBounds);
+        }
+        if self.rules.mode == GameMode::Salvo {
+            return Err(RuleError::WrongMode(GameMode::Salvo));
+        }
+        if self.status != Progress {
+            return Err(RuleError::NotInProgress);
+        }
+        if requester != self.current_turn {
+            return Err(RuleError::NotYourTurn);
+        }
+
+        let enemy = [self.p1.as_mut(), self.p2.as_mut()]
+            .into_iter()
+            .flatten()
+            .find(|p| p.name != requester)
+            .ok_or(RuleError::NotAPlayer)?;
+        let result = enemy.fire(hit, &self.rules);
+        let enemy_name = enemy.name.clone();
+        let all_destroyed = enemy.is_all_destroyed();
+        if result != ShotResult::Repeat {
+            self.player_mut(&requester).unwrap().shots_fired += 1;
+        }
+        let mut events = vec![GameEvent::Shot {
+            player: requester,
+            outcome: ShotOutcome {
+                x: hit.x,
+                y: hit.y,
+                result,
+            },
+        }];
+
+        let pass_turn = match (result, self.rules.turn_policy) {
+            (ShotResult::Repeat, _) => false, //already shot there, shooter picks another cell
+            (ShotResult::Miss, _) => true,
+            (_, TurnPolicy::Alternate) => true,
+            (_, TurnPolicy::ExtraTurnOnHit) => false, //don't change current turn player on hit
+        };
+        if all_destroyed {
+            events.push(self.finish(&enemy_name, GameOverReason::AllShipsSunk));
+            return Ok(events);
+        }
+
+        self.charge_clock();
+        if pass_turn {
+            self.current_turn = enemy_name.clone();
+            events.push(GameEvent::TurnPassed { to: enemy_name });
+        }
+
+        if result != ShotResult::Repeat {
+            self.reset_turn_clock();
+        }
+
+        Ok(events)
+    }
+
+    /// All shots are resolved at once, then the turn always passes
+    fn salvo(
+        &mut self,
+        requester: String,
+        shots: Vec<Point2d>,
+    ) -> Result<Vec<GameEvent>, RuleError> {
+        if self.rules.mode != GameMode::Salvo {
+            return Err(RuleError::WrongMode(GameMode::Classic));
+        }
+        if self.status != Progress {
+            return Err(RuleError::NotInProgress);
+        }
+        if requester != self.current_turn {
+            return Err(RuleError::NotYourTurn);
+        }
+        let allowed = self.player(&requester).map_or(0, |p| p.alive_ships());
+        if shots.is_empty() || shots.len() > allowed {
+            return Err(RuleError::SalvoSize { allowed });
+        }
+        let mut unique = HashSet::new();
+        for &hit in shots.iter() {
+            if !self.rules.contains(hit) {
+                return Err(RuleError::OutOfBounds);
+            }
+            if !unique.insert(hit) {
+                return Err(RuleError::DuplicateShot);
+            }
+        }
+
+        let enemy = [self.p1.as_mut(), self.p2.as_mut()]
+            .into_iter()
+            .flatten()
+            .find(|p| p.name != requester)
+            .ok_or(RuleError::NotAPlayer)?;
+        let outcomes: Vec<ShotOutcome> = shots
+            .into_iter()
+            .map(|hit| ShotOutcome {
+                x: hit.x,
+                y: hit.y,
+                result: enemy.fire(hit, &self.rules),
+            })
+            .collect();
+        let enemy_name = enemy.name.clone();
+        let all_destroyed = enemy.is_all_destroyed();
+
+        let fired = outcomes
+            .iter()
+            .filter(|r| r.result != ShotResult::Repeat)
+            .count();
+        self.player_mut(&requester).unwrap().shots_fired += fired;
+        let mut events: Vec<GameEvent> = outcomes
+            .into_iter()
+            .map(|outcome| GameEvent::Shot {
+                player: requester.clone(),
+                outcome,
+            })
+            .collect();
+
+        if all_destroyed {
+            events.push(self.finish(&enemy_name, GameOverReason::AllShipsSunk));
+            return Ok(events);
+        }
+
+        self.charge_clock();
+        self.current_turn = enemy_name.clone();
+        self.reset_turn_clock();
+        events.push(GameEvent::TurnPassed { to: enemy_name });
+        Ok(events)
+    }
+
+    /// Registers a rematch fleet, once both players offered the game restarts
+    /// with the previous loser shooting first.
+    fn offer_rematch(
+        &mut self,
+        name: String,
+        ships: ShipsRaw,
+    ) -> Result<Vec<GameEvent>, RuleError> {
+        if self.status != GameOver {
+            return Err(RuleError::NotOver);
+        }
+        if self.player(&name).is_none() {
+            return Err(RuleError::NotAPlayer);
+        }
+        validate_fleet(&ships, &self.rules)?;
+        self.rematch_offers.insert(name.clone(), ships);
+        let mut events = vec![GameEvent::RematchOffered { player: name }];
+
+        let (Some(p1), Some(p2)) = (self.p1.as_ref(), self.p2.as_ref()) else {
+            return Ok(events);
+        };
+        let (p1_name, p2_name) = (p1.name.clone(), p2.name.clone());
+        if !self.rematch_offers.contains_key(&p1_name)
+            || !self.rematch_offers.contains_key(&p2_name)
+        {
+            return Ok(events);
+        }
+
+        let p1_ships = self.rematch_offers.remove(&p1_name).unwrap();
+        let p2_ships = self.rematch_offers.remove(&p2_name).unwrap();
+        if self.series_winner().is_some() {
+            // a rematch after a decided series starts a new one
+            self.score.clear();
+        }
+        events.extend(self.restart(p1_ships, p2_ships));
+
+        Ok(events)
+    }
+
+    /// Starts the next round of an undecided series, nothing happens otherwise
+    fn next_round(&mut self) -> Vec<GameEvent> {
+        if self.status != GameOver || self.rules.best_of <= 1 || self.series_winner().is_some() {
+            return vec![];
+        }
+
+        let p1_ships = self.p1.as_ref().unwrap().fleet();
+        let p2_ships = self.p2.as_ref().unwrap().fleet();
+        self.restart(p1_ships, p2_ships)
+    }
+
+    /// New boards for the same two players, previous loser shoots first
+    fn restart(&mut self, p1_ships: ShipsRaw, p2_ships: ShipsRaw) -> Vec<GameEvent> {
+        let p1_name = self.p1.as_ref().unwrap().name.clone();
+        let p2_name = self.p2.as_ref().unwrap().name.clone();
+        let mut events = vec![
+            GameEvent::Placed {
+                player: p1_name.clone(),
+                ships: p1_ships.clone(),
+            },
+            GameEvent::Placed {
+                player: p2_name.clone(),
+                ships: p2_ships.clone(),
+            },
+        ];
+        self.p1 = Some(Player::new(
+            p1_name.clone(),
+            ships_from_raw(p1_ships),
+            &self.rules,
+        ));
+        self.p2 = Some(Player::new(p2_name, ships_from_raw(p2_ships), &self.rules));
+
+        self.current_turn = self.result.take().map_or(p1_name, |r| r.loser);
+        self.rematch_offers.clear();
+        self.round += 1;
+        self.status = Progress;
+        self.reset_turn_clock();
+        self.charge_clock();
+
+        events.push(GameEvent::Started {
+            round: self.round,
+            first: self.current_turn.clone(),
+        });
+        events
+    }
+
+    /// Applies a logged event without checking it, the rules did when it was logged
+    fn redo(&mut self, event: &GameEvent) {
+        match event {
+            GameEvent::Placed { player, ships } => {
+                let placed =
+                    Player::new(player.clone(), ships_from_raw(ships.clone()), &self.rules);
+                if let Some(p) = self.player_mut(player) {
+                    *p = placed;
+                } else if self.p1.is_none() {
+                    self.p1 = Some(placed);
+                } else {
+                    self.p2 = Some(placed);
+                }
+            }
+            GameEvent::Joined { .. } | GameEvent::RematchOffered { .. } => {}
+            GameEvent::Started { round, first } => {
+                if self.series_winner().is_some() {
+                    self.score.clear();
+                }
+                self.round = *round;
+                self.current_turn = first.clone();
+                self.status = Progress;
+                self.result = None;
+            }
+            GameEvent::Shot { player, outcome } => {
+                let hit = Point2d::new(outcome.x, outcome.y);
+                let enemy = [self.p1.as_mut(), self.p2.as_mut()]
+                    .into_iter()
+                    .flatten()
+                    .find(|p| p.name != *player);
+                let fired = enemy.is_some_and(|e| e.fire(hit, &self.rules) != ShotResult::Repeat);
+                if let Some(shooter) = self.player_mut(player).filter(|_| fired) {
+                    shooter.shots_fired += 1;
+                }
+            }
+            GameEvent::TurnPassed { to } => self.current_turn = to.clone(),
+            GameEvent::GameOver(result) => {
+                *self.score.entry(result.winner.clone()).or_insert(0) += 1;
+                self.status = GameOver;
+                self.result = Some(result.clone());
+            }
+        }
+        self.log.push(event.clone());
+    }
+
+    /// Applies flag fall or the turn timeout policy if a deadline has passed
+    fn timeout(&mut self) -> Vec<GameEvent> {
+        if self.status != Progress {
+            return vec![];
+        }
+
+        let idle = self.current_turn.clone();
+        if self.clock_left(&idle).is_some_and(|left| left.is_zero()) {
+            return vec![self.finish(&idle, GameOverReason::FlagFall)];
+        }
+
+        let expired = self.turn_deadline.is_some_and(|d| d <= Instant::now());
+        let Some(timer) = self.rules.turn_timer else {
+            return vec![];
+        };
+        if !expired {
+            return vec![];
+        }
+
+        match timer.on_timeout {
+            TimeoutPolicy::Forfeit => vec![self.finish(&idle, GameOverReason::TurnTimeout)],
+            TimeoutPolicy::RandomShot => {
+                let mut targets = self
+                    .opponent_of(&idle)
+                    .map(Player::untouched_cells)
+                    .unwrap_or_default();
+                targets.shuffle(&mut self.rng);
+                let moved = match self.rules.mode {
+                    GameMode::Classic => match targets.pop() {
+                        Some(hit) => self.shoot(idle, hit),
+                        None => return vec![],
+                    },
+                    GameMode::Salvo => {
+                        let count = self.player(&idle).map_or(0, |p| p.alive_ships());
+                        targets.truncate(count);
+                        self.salvo(idle, targets)
+                    }
+                };
+                moved.unwrap_or_default()
+            }
+        }
+    }
+
+    pub fn series_winner(&self) -> Option<ClientId> {
+        let needed = self.rules.wins_needed();
+        self.score
+            .iter()
+            .find(|(_, wins)| **wins >= needed)
+            .map(|(name, _)| name.clone())
+    }
+
+    pub fn series_score(&self) -> Option<SeriesScore> {
+        if self.rules.best_of <= 1 {
+            return None;
+        }
+
+        Some(SeriesScore {
+            best_of: self.rules.best_of,
+            round: self.round,
+            score: self.score.clone(),
+            winner: self.series_winner(),
+        })
+    }
+
+    /// Ends the game, the opponent of `loser` wins
+    fn finish(&mut self, loser: &str, reason: GameOverReason) -> GameEvent {
+        self.charge_clock();
+        self.status = GameOver;
+        self.reset_turn_clock();
+        self.clock_started = None;
+
+        let winner = self
+            .opponent_of(loser)
+            .map(|p| p.name.clone())
+            .unwrap_or_default();
+        let shots = [self.p1.as_ref(), self.p2.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|p| (p.name.clone(), p.shots_fired))
+            .collect();
+        *self.score.entry(winner.clone()).or_insert(0) += 1;
+        let result = GameResult {
+            winner,
+            loser: loser.to_string(),
+            reason,
+            shots,
+        };
+        self.result = Some(result.clone());
+        GameEvent::GameOver(result)
+    }
+
+    /// Subtracts the time spent since the last charge from the turning player's clock.
+    /// Call before `current_turn` changes.
+    fn charge_clock(&mut self) {
+        let now = Instant::now();
+        if let Some(started) = self.clock_started {
+            let name = self.current_turn.clone();
+            if let Some(p) = self.player_mut(&name) {
+                p.time_left = p.time_left.map(|t| t.saturating_sub(now - started));
+            }
+        }
+
+        self.clock_started = match (self.status, self.rules.clock_seconds) {
+            (Progress, Some(_)) => Some(now),
+            _ => None,
+        };
+    }
+
+    /// Chess clock of the player including the currently running turn
+    pub fn clock_left(&self, name: &str) -> Option<Duration> {
+        let left = self.player(name)?.time_left?;
+        match self.clock_started {
+            Some(started) if self.current_turn == name => {
+                Some(left.saturating_sub(started.elapsed()))
+            }
+            _ => Some(left),
+        }
+    }
+
+    /// Earliest moment one of the timers runs out
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let flag_fall = self
+            .clock_started
+            .zip(self.player(&self.current_turn).and_then(|p| p.time_left))
+            .map(|(started, left)| started + left);
+
+        match (self.turn_deadline, flag_fall) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Restarts the move countdown, call whenever a move was made
+    fn reset_turn_clock(&mut self) {
+        self.turn_deadline = match (self.status, self.rules.turn_timer) {
+            (Progress, Some(timer)) => Some(Instant::now() + Duration::from_secs(timer.seconds)),
+            _ => None,
+        };
+    }
+
+    pub fn player(&self, name: &str) -> Option<&Player> {
+        [self.p1.as_ref(), self.p2.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|p| p.name == name)
+    }
+
+    fn player_mut(&mut self, name: &str) -> Option<&mut Player> {
+        [self.p1.as_mut(), self.p2.as_mut()]
+            .into_iter()
+            .flatten()
+            .find(|p| p.name == name)
+    }
+
+    pub fn opponent_of(&self, name: &str) -> Option<&Player> {
+        [self.p1.as_ref(), self.p2.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|p| p.name != name)
+    }
+}
+
+fn ships_from_raw(ships: ShipsRaw) -> Vec<Ship> {
+    ships
+        .into_iter()
+        .map(|ship| Ship::new(ship.into_iter().map(|(x, y)| Point2d::new(x, y)).collect()))
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Player {
+    pub name: String,
+    pub grid_state: Vec<Vec<CellType>>,
+    pub ships: Vec<Ship>,
+    /// Index into `ships` for every ship cell
+    pub ship_at: HashMap<Point2d, usize>,
+    /// Chess clock, charged by `GameCore::charge_clock`
+    pub time_left: Option<Duration>,
+    pub shots_fired: usize,
+}
+
+impl Player {
+    pub fn new(name: String, ships: Vec<Ship>, rules: &RuleSet) -> Self {
+        let mut ship_at = HashMap::new();
+        for (idx, s) in ships.iter().enumerate() {
+            for xy in &s.coords {
+                ship_at.insert(*xy, idx);
+            }
+        }
+
+        Self {
+            name,
+            grid_state: {
+                let mut state = vec![vec![CellType::EmptyNoShip; rules.width]; rules.height];
+                for point in ship_at.keys() {
+                    state[point.x][point.y] = CellType::HasShip
+                }
+                state
+            },
+            ships,
+            ship_at,
+            time_left: rules.clock_seconds.map(Duration::from_secs),
+            shots_fired: 0,
+        }
+    }
+
+    /// The fleet as it was placed
+    pub fn fleet(&self) -> ShipsRaw {
+        self.ships
+            .iter()
+            .map(|ship| ship.coords.iter().map(|p| (p.x, p.y)).collect())
+            .collect()
+    }
+
+    pub fn ship(&self, p: Point2d) -> Option<&Ship> {
+        self.ship_at.get(&p).map(|&idx| &self.ships[idx])
+    }
+
+    pub fn alive_ships(&self) -> usize {
+        self.ships.iter().filter(|s| !s.is_dead()).count()
+    }
+
+    pub fn is_all_destroyed(&self) -> bool {
+        self.ships.iter().all(Ship::is_dead)
+    }
+
+    /// Cells of the player's grid that were never shot or revealed
+    pub fn untouched_cells(&self) -> Vec<Point2d> {
+        let mut cells = vec![];
+        for (x, row) in self.grid_state.iter().enumerate() {
+            for (y, cell) in row.iter().enumerate() {
+                if let CellType::EmptyNoShip | CellType::HasShip = cell {
+                    cells.push(Point2d::new(x, y));
+                }
+            }
+        }
+        cells
+    }
+
+    fn fire(&mut self, hit: Point2d, rules: &RuleSet) -> ShotResult {
+        match self.grid_state[hit.x][hit.y] {
+            CellType::EmptyNoShip => {
+                self.grid_state[hit.x][hit.y] = CellType::EmptyMissed;
+                ShotResult::Miss
+            }
+            CellType::HasShip => {
+                let ship = &mut self.ships[self.ship_at[&hit]];
+                let mark_as_hit_after_kill = ship.hit(rules);
+                let sunk = ship.is_dead();
+                self.grid_state[hit.x][hit.y] = CellType::HasShipHit;
+
+                if rules.auto_reveal {
+                    for p in mark_as_hit_after_kill {
+                        //keep real misses as they are
+                        if self.grid_state[p.x][p.y] == CellType::EmptyNoShip {
+                            self.grid_state[p.x][p.y] = CellType::AutoRevealed;
+                        }
+                    }
+                }
+
+                if sunk {
+                    ShotResult::Sunk
+                } else {
+                    ShotResult::Hit
+                }
+            }
+            CellType::EmptyMissed => ShotResult::Repeat, //already miss at prev turn, do nothing
+            CellType::HasShipHit => ShotResult::Repeat,  //already hit at prev turn, do nothing
+            CellType::AutoRevealed => ShotResult::Repeat, //known to be empty, do nothing
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Copy)]
+pub enum CellType {
+    #[default]
+    EmptyNoShip,
+    HasShip,
+    EmptyMissed, // miss
+    HasShipHit,
+    AutoRevealed, // empty, revealed by the server around a sunk ship
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct Point2d {
+    pub x: usize,
+    pub y: usize,
+}
+
+impl Point2d {
+    pub fn new(x: usize, y: usize) -> Self {
+        Self { x, y }
+    }
+}
+
+#[derive(Debug)]
+pub struct Ship {
+    pub coords: Vec<Point2d>,
+    pub health: usize,
+}
+
+impl Ship {
+    pub fn new(coords: Vec<Point2d>) -> Self {
+        Self {
+            health: coords.len(),
+            coords,
+        }
+    }
+
+    /// Returns the ring of cells around the ship once it's sunk, empty set otherwise
+    pub fn hit(&mut self, rules: &RuleSet) -> HashSet<Point2d> {
+        self.health -= 1;
+
+        let mut set = HashSet::new();
+        if !self.is_dead() {
+            return set;
+        }
+
+        for p in self.coords.iter() {
+            set.extend(rules.neighbours(*p));
+        }
+
+        for p in self.coords.iter() {
+            set.remove(p);
+        }
+
+        set
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::tests::classic_fleet;
+    use crate::rules::TurnTimer;
+
+    /// Both players joined with the classic fleet, "a" moves first
+    fn started(rules: RuleSet) -> GameCore {
+        let mut game = GameCore::new(rules, 1).with_first("a");
+        for player in ["a", "b"] {
+            let join = Action::Join {
+                player: player.to_string(),
+                ships: classic_fleet(),
+            };
+            game.apply(join).unwrap();
+        }
+        game
+    }
+
+    fn shoot(game: &mut GameCore, player: &str, x: usize, y: usize) -> Vec<GameEvent> {
+        let shot = Action::Shoot {
+            player: player.to_string(),
+            at: Point2d::new(x, y),
+        };
+        game.apply(shot).unwrap()
+    }
+
+    fn last_result(events: &[GameEvent]) -> Option<&GameResult> {
+        match events.last() {
+            Some(GameEvent::GameOver(result)) => Some(result),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn second_join_starts_the_game() {
+        let mut game = GameCore::new(RuleSet::classic(), 1).with_first("b");
+        let join = |player: &str| Action::Join {
+            player: player.to_string(),
+            ships: classic_fleet(),
+        };
+        game.apply(join("a")).unwrap();
+        assert_eq!(game.status, WaitingPlayers);
+        assert_eq!(game.apply(join("a")), Err(RuleError::AlreadyJoined));
+
+        let events = game.apply(join("b")).unwrap();
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::Started {
+                round: 1,
+                first: "b".to_string()
+            })
+        );
+        assert_eq!(game.status, Progress);
+        assert_eq!(game.current_turn, "b");
+        assert_eq!(game.apply(join("c")), Err(RuleError::GameFull));
+    }
+
+    #[test]
+    fn hit_keeps_the_turn_by_default() {
+        let mut game = started(RuleSet::classic());
+        shoot(&mut game, "a", 0, 0);
+        assert_eq!(game.current_turn, "a");
+
+        let events = shoot(&mut game, "a", 1, 0);
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::TurnPassed { to: "b".into() })
+        );
+        assert_eq!(game.current_turn, "b");
+    }
+
+    #[test]
+    fn alternate_policy_passes_the_turn_on_hit() {
+        let rules = RuleSet {
+            turn_policy: TurnPolicy::Alternate,
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        shoot(&mut game, "a", 0, 0);
+        assert_eq!(game.current_turn, "b");
+    }
+
+    #[test]
+    fn repeated_shot_keeps_the_turn_and_is_not_counted() {
+        let mut game = started(RuleSet::classic());
+        shoot(&mut game, "a", 0, 0);
+        let events = shoot(&mut game, "a", 0, 0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(game.current_turn, "a");
+        assert_eq!(game.player("a").unwrap().shots_fired, 1);
+    }
+
+    #[test]
+    fn rejected_shot_leaves_the_game_untouched() {
+        let mut game = started(RuleSet::classic());
+        let log = game.log.len();
+        let out_of_turn = Action::Shoot {
+            player: "b".to_string(),
+            at: Point2d::new(0, 0),
+        };
+        assert_eq!(game.apply(out_of_turn), Err(RuleError::NotYourTurn));
+        let off_board = Action::Shoot {
+            player: "a".to_string(),
+            at: Point2d::new(10, 0),
+        };
+        assert_eq!(game.apply(off_board), Err(RuleError::OutOfBounds));
+        assert_eq!(game.log.len(), log);
+        assert_eq!(game.player("b").unwrap().untouched_cells().len(), 100);
+    }
+
+    #[test]
+    fn sinking_the_fleet_wins() {
+        let mut game = started(RuleSet::classic());
+        let mut events = vec![];
+        for &(x, y) in classic_fleet().iter().flatten() {
+            events = shoot(&mut game, "a", x, y);
+        }
+        let result = last_result(&events).unwrap();
+        assert_eq!(result.winner, "a");
+        assert_eq!(result.reason, GameOverReason::AllShipsSunk);
+        assert_eq!(result.shots["a"], 20);
+        assert_eq!(game.status, GameOver);
+        assert_eq!(game.score["a"], 1);
+    }
+
+    #[test]
+    fn salvo_size_follows_ships_afloat() {
+        let rules = RuleSet {
+            mode: GameMode::Salvo,
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        let salvo = |shots: Vec<(usize, usize)>| Action::Salvo {
+            player: "a".to_string(),
+            shots: shots.into_iter().map(|(x, y)| Point2d::new(x, y)).collect(),
+        };
+
+        let too_many: Vec<(usize, usize)> = (0..10).map(|y| (9, y)).chain([(8, 0)]).collect();
+        assert_eq!(
+            game.apply(salvo(too_many)),
+            Err(RuleError::SalvoSize { allowed: 10 })
+        );
+        assert_eq!(
+            game.apply(salvo(vec![(9, 0), (9, 0)])),
+            Err(RuleError::DuplicateShot)
+        );
+        let single = Action::Shoot {
+            player: "a".to_string(),
+            at: Point2d::new(9, 0),
+        };
+        assert_eq!(
+            game.apply(single),
+            Err(RuleError::WrongMode(GameMode::Salvo))
+        );
+
+        // hits do not keep the turn in salvo mode
+        let events = game.apply(salvo(vec![(6, 0), (6, 2), (0, 0)])).unwrap();
+        assert_eq!(events.len(), 4);
+        assert_eq!(game.current_turn, "b");
+        // two ships down, b answers with eight shots at most
+        assert_eq!(game.player("b").unwrap().alive_ships(), 8);
+        assert_eq!(
+            game.apply(Action::Salvo {
+                player: "b".to_string(),
+                shots: vec![],
+            }),
+            Err(RuleError::SalvoSize { allowed: 8 })
+        );
+    }
+
+    #[test]
+    fn timeout_forfeits_only_after_the_deadline() {
+        let rules = RuleSet {
+            turn_timer: Some(TurnTimer {
+                seconds: 30,
+                on_timeout: TimeoutPolicy::Forfeit,
+            }),
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        assert_eq!(game.apply(Action::Timeout), Ok(vec![]));
+
+        game.turn_deadline = Some(Instant::now() - Duration::from_millis(1));
+        let events = game.apply(Action::Timeout).unwrap();
+        let result = last_result(&events).unwrap();
+        assert_eq!(result.loser, "a");
+        assert_eq!(result.reason, GameOverReason::TurnTimeout);
+    }
+
+    #[test]
+    fn timeout_can_shoot_for_the_idle_player() {
+        let rules = RuleSet {
+            turn_timer: Some(TurnTimer {
+                seconds: 30,
+                on_timeout: TimeoutPolicy::RandomShot,
+            }),
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        game.turn_deadline = Some(Instant::now() - Duration::from_millis(1));
+        let events = game.apply(Action::Timeout).unwrap();
+        assert!(matches!(
+            events.first(),
+            Some(GameEvent::Shot { player, .. }) if player == "a"
+        ));
+        assert_eq!(game.status, Progress);
+        assert!(game.turn_deadline.unwrap() > Instant::now());
+    }
+
+    #[test]
+    fn empty_chess_clock_loses() {
+        let rules = RuleSet {
+            clock_seconds: Some(60),
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        assert_eq!(game.apply(Action::Timeout), Ok(vec![]));
+
+        game.p1.as_mut().unwrap().time_left = Some(Duration::ZERO);
+        let events = game.apply(Action::Timeout).unwrap();
+        let result = last_result(&events).unwrap();
+        assert_eq!(result.loser, "a");
+        assert_eq!(result.reason, GameOverReason::FlagFall);
+    }
+
+    #[test]
+    fn rematch_restarts_with_the_loser_first() {
+        let mut game = started(RuleSet::classic());
+        let rematch = |player: &str| Action::Rematch {
+            player: player.to_string(),
+            ships: classic_fleet(),
+        };
+        assert_eq!(game.apply(rematch("a")), Err(RuleError::NotOver));
+
+        game.apply(Action::Resign {
+            player: "b".to_string(),
+        })
+        .unwrap();
+        let events = game.apply(rematch("a")).unwrap();
+        assert_eq!(
+            events,
+            vec![GameEvent::RematchOffered {
+                player: "a".to_string()
+            }]
+        );
+        assert_eq!(game.status, GameOver);
+
+        let events = game.apply(rematch("b")).unwrap();
+        assert_eq!(
+            events.last(),
+            Some(&GameEvent::Started {
+                round: 2,
+                first: "b".to_string()
+            })
+        );
+        assert_eq!(game.status, Progress);
+        assert_eq!(game.result, None);
+        assert_eq!(game.player("a").unwrap().untouched_cells().len(), 100);
+    }
+
+    #[test]
+    fn series_ends_once_a_player_has_enough_wins() {
+        let rules = RuleSet {
+            best_of: 3,
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        let resign = |player: &str| Action::Resign {
+            player: player.to_string(),
+        };
+        assert_eq!(game.apply(Action::NextRound), Ok(vec![]));
+
+        game.apply(resign("a")).unwrap();
+        assert_eq!(game.series_winner(), None);
+        let events = game.apply(Action::NextRound).unwrap();
+        assert!(matches!(
+            events.last(),
+            Some(GameEvent::Started { round: 2, first }) if first == "a"
+        ));
+
+        game.apply(resign("a")).unwrap();
+        assert_eq!(game.series_winner(), Some("b".to_string()));
+        let score = game.series_score().unwrap();
+        assert_eq!((score.round, score.score["b"]), (2, 2));
+        assert_eq!(game.apply(Action::NextRound), Ok(vec![]));
+    }
+
+    #[test]
+    fn next_round_keeps_the_placed_fleets() {
+        let rules = RuleSet {
+            best_of: 3,
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        shoot(&mut game, "a", 0, 0);
+        game.apply(Action::Resign {
+            player: "b".to_string(),
+        })
+        .unwrap();
+
+        game.apply(Action::NextRound).unwrap();
+        for name in ["a", "b"] {
+            let player = game.player(name).unwrap();
+            assert_eq!(player.fleet(), classic_fleet());
+            assert_eq!(player.untouched_cells().len(), 100);
+        }
+    }
+
+    #[test]
+    fn after_shots_stops_before_the_next_shot() {
+        let mut game = started(RuleSet::classic());
+        shoot(&mut game, "a", 0, 0);
+        shoot(&mut game, "a", 5, 5);
+        shoot(&mut game, "b", 6, 6);
+
+        let start = game.after_shots(0);
+        assert_eq!(start.status, Progress);
+        assert_eq!(start.player("b").unwrap().untouched_cells().len(), 100);
+
+        // the miss passed the turn, that belongs to the second shot
+        let two = game.after_shots(2);
+        assert_eq!(two.current_turn, "b");
+        assert_eq!(two.player("a").unwrap().shots_fired, 2);
+        assert_eq!(two.player("b").unwrap().shots_fired, 0);
+
+        assert_eq!(game.after_shots(3).log, game.log);
+        assert_eq!(game.after_shots(100).log, game.log);
+    }
+
+    #[test]
+    fn restore_replays_the_log() {
+        let mut game = started(RuleSet::classic());
+        shoot(&mut game, "a", 0, 0);
+        shoot(&mut game, "a", 5, 5);
+        shoot(&mut game, "b", 6, 6);
+
+        let restored = GameCore::restore(game.rules.clone(), game.seed, &game.log);
+        assert_eq!(restored.log, game.log);
+        assert_eq!(restored.current_turn, game.current_turn);
+        for name in ["a", "b"] {
+            let (live, back) = (game.player(name).unwrap(), restored.player(name).unwrap());
+            assert_eq!(live.grid_state, back.grid_state);
+            assert_eq!(live.shots_fired, back.shots_fired);
+        }
+    }
+
+    #[test]
+    fn restore_keeps_the_chess_clocks() {
+        let rules = RuleSet {
+            clock_seconds: Some(60),
+            ..RuleSet::classic()
+        };
+        let mut game = started(rules);
+        game.p1.as_mut().unwrap().time_left = Some(Duration::from_millis(12_345));
+        let clocks = game.clocks();
+        assert_eq!(
+            clocks,
+            HashMap::from([("a".to_string(), 12_345), ("b".to_string(), 60_000)])
+        );
+
+        let restored =
+            GameCore::restore(game.rules.clone(), game.seed, &game.log).with_clocks(&clocks);
+        assert_eq!(
+            restored.player("a").unwrap().time_left,
+            Some(Duration::from_millis(12_345))
+        );
+        assert_eq!(
+            restored.player("b").unwrap().time_left,
+            Some(Duration::from_secs(60))
+        );
+        // without a chess clock there is nothing to set
+        let plain = started(RuleSet::classic()).with_clocks(&clocks);
+        assert_eq!(plain.player("a").unwrap().time_left, None);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::dto::{GameId, WsEvent};
+use crate::engine::RuleError;
+use std::fmt;
+
+/// Why a request was not served. The socket loop answers with `BadRequestRs`,
+/// or `ServerAbort` when the server itself is at fault, and keeps the connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerError {
+    /// Text frame that is not a `WsEvent`
+    Malformed(String),
+    NoSuchGame(GameId),
+    AlreadyInGame,
+    NotInGame,
+    InvalidRules(String),
+    Rule(RuleError),
+    /// Chat message the game does not accept
+    ChatRejected(String),
+    RateLimited,
+    UnknownBot,
+    BotConnected,
+    /// Broken invariant, e.g. a running game without its clients
+    Internal(String),
+}
+
+impl ServerError {
+    /// Stable name of the error for clients, the message is for humans
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServerError::Malformed(_) => "malformed",
+            ServerError::NoSuchGame(_) => "noSuchGame",
+            ServerError::AlreadyInGame => "alreadyInGame",
+            ServerError::NotInGame => "notInGame",
+            ServerError::InvalidRules(_) => "invalidRules",
+            ServerError::Rule(e) => match e {
+                RuleError::Placement(_) => "placement",
+                RuleError::GameFull => "gameFull",
+                RuleError::AlreadyJoined => "alreadyJoined",
+                RuleError::NotAPlayer => "notAPlayer",
+                RuleError::NotInProgress => "notInProgress",
+                RuleError::NotOver => "notOver",
+                RuleError::NotYourTurn => "notYourTurn",
+                RuleError::OutOfBounds => "outOfBounds",
+                RuleError::WrongMode(_) => "wrongMode",
+                RuleError::SalvoSize { .. } => "salvoSize",
+                RuleError::DuplicateShot => "duplicateShot",
+                RuleError::NoRandomFleet => "noRandomFleet",
+            },
+            ServerError::ChatRejected(_) => "chatRejected",
+            ServerError::RateLimited => "rateLimited",
+            ServerError::UnknownBot => "unknownBot",
+            ServerError::BotConnected => "botConnected",
+            ServerError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Malformed(e) => write!(f, "Malformed message: {}", e),
+            ServerError::NoSuchGame(game_id) => write!(f, "No such game: {}", game_id),
+            ServerError::AlreadyInGame => write!(f, "Already in a game"),
+            ServerError::NotInGame => write!(f, "Not in a game"),
+            ServerError::InvalidRules(e) => write!(f, "{}", e),
+            ServerError::Rule(e) => write!(f, "{}", e),
+            ServerError::ChatRejected(e) => write!(f, "{}", e),
+            ServerError::RateLimited => write!(f, "Too many messages"),
+            ServerError::UnknownBot => write!(f, "Unknown bot token"),
+            ServerError::BotConnected => write!(f, "Bot already connected"),
+            ServerError::Internal(e) => write!(f, "Internal error: {}", e),
+        }
+    }
+}
+
+impl From<RuleError> for ServerError {
+    fn from(e: RuleError) -> Self {
+        ServerError::Rule(e)
+    }
+}
+
+impl From<ServerError> for WsEvent {
+    fn from(e: ServerError) -> Self {
+        let (code, message) = (e.code().to_string(), e.to_string());
+        match e {
+            ServerError::Internal(_) => WsEvent::ServerAbort { code, message },
+            _ => WsEvent::BadRequestRs { code, message },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{GameMode, PlacementError};
+
+    fn all() -> Vec<(ServerError, &'static str)> {
+        let rule = |e: RuleError| ServerError::Rule(e);
+        vec![
+            (ServerError::Malformed("eof".to_string()), "malformed"),
+            (ServerError::NoSuchGame("abc".to_string()), "noSuchGame"),
+            (ServerError::AlreadyInGame, "alreadyInGame"),
+            (ServerError::NotInGame, "notInGame"),
+            (ServerError::InvalidRules("0x0".to_string()), "invalidRules"),
+            (
+                rule(RuleError::Placement(PlacementError::EmptyShip)),
+                "placement",
+            ),
+            (rule(RuleError::GameFull), "gameFull"),
+            (rule(RuleError::AlreadyJoined), "alreadyJoined"),
+            (rule(RuleError::NotAPlayer), "notAPlayer"),
+            (rule(RuleError::NotInProgress), "notInProgress"),
+            (rule(RuleError::NotOver), "notOver"),
+            (rule(RuleError::NotYourTurn), "notYourTurn"),
+            (rule(RuleError::OutOfBounds), "outOfBounds"),
+            (rule(RuleError::WrongMode(GameMode::Salvo)), "wrongMode"),
+            (rule(RuleError::SalvoSize { allowed: 3 }), "salvoSize"),
+            (rule(RuleError::DuplicateShot), "duplicateShot"),
+            (rule(RuleError::NoRandomFleet), "noRandomFleet"),
+            (
+                ServerError::ChatRejected("empty".to_string()),
+                "chatRejected",
+            ),
+            (ServerError::RateLimited, "rateLimited"),
+            (ServerError::UnknownBot, "unknownBot"),
+            (ServerError::BotConnected, "botConnected"),
+            (ServerError::Internal("no clients".to_string()), "internal"),
+        ]
+    }
+
+    #[test]
+    fn every_error_has_its_code() {
+        for (e, code) in all() {
+            assert_eq!(e.code(), code, "{:?}", e);
+        }
+    }
+
+    #[test]
+    fn only_internal_errors_abort() {
+        for (e, code) in all() {
+            let message = e.to_string();
+            let expected = if code == "internal" {
+                WsEvent::ServerAbort {
+                    code: code.to_string(),
+                    message,
+                }
+            } else {
+                WsEvent::BadRequestRs {
+                    code: code.to_string(),
+                    message,
+                }
+            };
+            assert_eq!(WsEvent::from(e), expected);
+        }
+    }
+
+    #[test]
+    fn rule_errors_keep_their_message() {
+        let e: ServerError = RuleError::NotYourTurn.into();
+        assert_eq!(e.to_string(), RuleError::NotYourTurn.to_string());
+        let e = ServerError::NoSuchGame("abc".to_string());
+        assert_eq!(e.to_string(), "No such game: abc");
+    }
+}
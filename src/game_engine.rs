@@ -1,44 +1,74 @@
-use crate::app_state::{CellType, Client, Game, GameClients, GameFlow, Player, Point2d, Wrapper};
+use crate::app_state::{Client, Game, GameClients, QueueEntry, Wrapper};
+use crate::bot;
+use crate::bot::BotLevel;
+use crate::chat::MAX_CHAT_LEN;
 use crate::dto::{
-    CreateGameRequest, GameStatus, Grid2D, GridDTO, GridResponse, JoinGameRequest, PlayerAction,
-    QueueRequest, StateRequest, TurnRequest, WsEvent,
+    ChatRequest, ClientId, CreateGameRequest, GameId, GameStatus, Grid2D, GridResponse,
+    JoinGameRequest, PlayBotRequest, PlayerAction, QueueRequest, RematchRequest, ResignRequest,
+    SalvoRequest, SpectateRequest, StateRequest, TurnRequest, WsEvent,
 };
+use crate::engine::{Action, CellType, GameEvent, Player, Point2d, RuleError};
+use crate::error::ServerError;
+use crate::rules::{validate_fleet, GameMode};
+use crate::storage::Write;
 use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 use GameStatus::{GameOver, Progress, WaitingPlayers};
 
 pub fn game_new(
     wrapper: Wrapper,
-    CreateGameRequest { username, ships }: CreateGameRequest,
-) -> WsEvent {
+    CreateGameRequest {
+        username,
+        ships,
+        rules,
+    }: CreateGameRequest,
+) -> Result<WsEvent, ServerError> {
     println!("game_new: {}", &username);
 
+    rules.validate().map_err(ServerError::InvalidRules)?;
+
     {
         let state = wrapper.shared.state.read().unwrap();
         if let Some(game_id) = state.client_games.get(&username) {
-            return WsEvent::CreateGameRs {
+            return Ok(WsEvent::CreateGameRs {
                 game_id: game_id.clone(),
                 status: Progress,
-            };
+            });
         }
     } //drop lock
 
-    let game_id = wrapper.create_game();
-    let mut state = wrapper.shared.state.write().unwrap();
+    let mut game = Game::new(rules.clone(), wrapper.next_seed());
+    // a fixed GAME_SEED hands out the same ids again after a restart
+    while wrapper.game_id_taken(&game.id) {
+        println!("Game id {} is taken, drawing another seed", &game.id);
+        game = Game::new(rules.clone(), wrapper.next_seed());
+    }
+    println!("Game {} seed {}", &game.id, game.core.seed);
+    let join = Action::Join {
+        player: username.clone(),
+        ships,
+    };
+    if let Err(e) = game.core.apply(join) {
+        println!("Rejected placement: {} {}", &username, e);
+        return Err(e.into());
+    }
 
-    // let mut ng = Game::new();
-    let ng = state.games.get_mut(&game_id).unwrap();
-    ng.join(username.clone(), ships);
+    let game_id = game.id.clone();
+    wrapper.shared.writer.send(Write::Game(game.saved()));
+    let mut state = wrapper.shared.state.write().unwrap();
+    state.games.insert(game_id.clone(), game);
     state.client_games.insert(username.clone(), game_id.clone());
     state.game_clients.insert(
         game_id.clone(),
         GameClients(username.clone(), String::new()),
     );
 
-    return WsEvent::CreateGameRs {
+    Ok(WsEvent::CreateGameRs {
         game_id: game_id.clone(),
         status: WaitingPlayers,
-    };
+    })
 }
 
 pub fn game_join(
@@ -48,142 +78,677 @@ pub fn game_join(
         username,
         ships,
     }: JoinGameRequest,
-) -> WsEvent {
+) -> Result<WsEvent, ServerError> {
     println!("game_join: {} {}", &game_id, &username);
 
-    {
-        let state = wrapper.shared.state.read().unwrap();
+    let state = &mut wrapper.shared.state.write().unwrap();
+    if let Some(g) = state.client_games.get(&username) {
+        println!("P1 already in a game: {} {}", &g, &username);
+        return Err(ServerError::AlreadyInGame);
+    }
 
-        if let None = state.games.get(&game_id) {
-            println!("Joining nox-existing game: {} {}", &game_id, &username);
-            return WsEvent::ServerAbort;
-        }
+    let Some(game) = state.games.get_mut(&game_id) else {
+        println!("Joining nox-existing game: {} {}", &game_id, &username);
+        return Err(ServerError::NoSuchGame(game_id));
+    };
+    let Some(p1_name) = game.core.p1.as_ref().map(|p| p.name.clone()) else {
+        println!(
+            "P1 did not join at game creation (game logic error): {} {}",
+            &game_id, &username
+        );
+        return Err(ServerError::Internal("game without a creator".to_string()));
+    };
 
-        if let Some(g) = state.client_games.get(&username) {
-            println!("P1 already in a game: {} {}", &g, &username);
-            return WsEvent::ServerAbort;
-        }
+    let join = Action::Join {
+        player: username.clone(),
+        ships,
+    };
+    if let Err(e) = game.apply(join, &wrapper.shared.writer) {
+        println!("Rejected placement: {} {}", &username, e);
+        return Err(e.into());
+    }
+    let status = game.core.status;
+    let timed = game.core.rules.is_timed();
 
-        let game = state.games.get(&game_id).unwrap();
-        if game.p1.is_none() {
-            println!(
-                "P1 did not join at game creation (game logic error): {} {}",
-                &game_id, &username
-            );
-            return WsEvent::ServerAbort;
-        }
-    } //drop lock
+    state.client_games.insert(username.clone(), game_id.clone());
+    if let Some(clients) = state.game_clients.get_mut(&game_id) {
+        clients.1 = username.clone();
+    }
 
-    let state = &mut wrapper.shared.state.write().unwrap();
+    if status == Progress && timed {
+        start_game_clock(wrapper.clone(), game_id.clone());
+    }
 
-    let x = state.games.get_mut(&game_id).unwrap();
-    x.join(username.clone(), ships);
-    let (p1_name, has_p1, has_p2, status) = (
-        x.p1.as_ref().unwrap().name.clone(),
-        x.p1.is_some(),
-        x.p2.is_some(),
-        x.status,
-    );
+    Ok(WsEvent::JoinRs(GridResponse::new(status, None), p1_name))
+}
 
-    if has_p1 && has_p2 {
-        state.client_games.insert(username.clone(), game_id.clone());
-        state.game_clients.get_mut(&game_id).unwrap().1 = username.clone();
-    }
+/// New game against the server side bot, which takes the second seat right away
+pub fn game_vs_bot(
+    wrapper: Wrapper,
+    PlayBotRequest {
+        username,
+        ships,
+        rules,
+        level,
+    }: PlayBotRequest,
+) -> Result<WsEvent, ServerError> {
+    let response = game_new(
+        wrapper.clone(),
+        CreateGameRequest {
+            username,
+            ships,
+            rules,
+        },
+    )?;
+    let WsEvent::CreateGameRs {
+        game_id,
+        status: WaitingPlayers,
+    } = response
+    else {
+        return Err(ServerError::AlreadyInGame);
+    };
 
-    let g = state.games.get_mut(&game_id).unwrap();
-    if status != GameOver {
-        if let (Some(_), Some(_)) = (&g.p1, &g.p2) {
-            g.status = Progress;
-        } else {
-            g.status = WaitingPlayers;
+    // the bot is dealt from the game's seed, so the seed reproduces it
+    let (bot_id, rules, seed) = {
+        let state = &mut wrapper.shared.state.write().unwrap();
+        let game = state
+            .games
+            .get_mut(&game_id)
+            .ok_or_else(|| ServerError::NoSuchGame(game_id.clone()))?;
+        let core = &mut game.core;
+        (bot::bot_id(&mut core.rng), core.rules.clone(), core.seed)
+    };
+    let fleet = match bot::fleet(&rules, seed, 1) {
+        Ok(fleet) => fleet,
+        Err(e) => {
+            wrapper.remove_game(&game_id);
+            return Err(e.into());
         }
-    }
+    };
+    game_join(
+        wrapper.clone(),
+        JoinGameRequest {
+            game_id: game_id.clone(),
+            username: bot_id.clone(),
+            ships: fleet,
+        },
+    )?;
+    set_bot(&wrapper, &game_id, &bot_id, level);
+    wrapper.attach_client(
+        &game_id,
+        bot::spawn(wrapper.clone(), game_id.clone(), bot_id, level),
+    );
 
-    return WsEvent::JoinRs(GridResponse::new(status.clone(), None), p1_name);
+    Ok(WsEvent::CreateGameRs {
+        game_id,
+        status: Progress,
+    })
+}
+
+/// Remembers who the server bot is, storage needs it to bring the bot back
+fn set_bot(wrapper: &Wrapper, game_id: &str, bot_id: &str, level: BotLevel) {
+    let state = &mut wrapper.shared.state.write().unwrap();
+    let Some(game) = state.games.get_mut(game_id) else {
+        return;
+    };
+    game.bot = Some((bot_id.to_string(), level));
+    wrapper.shared.writer.send(Write::Game(game.saved()));
 }
 
 pub fn enqueue(
     wrapper: Wrapper,
-    QueueRequest { username, ships }: QueueRequest,
+    QueueRequest {
+        username,
+        ships,
+        rules,
+    }: QueueRequest,
     sender: Sender<WsEvent>,
-) -> WsEvent {
+) -> Result<WsEvent, ServerError> {
     println!("queue: {}", &username);
 
+    rules.validate().map_err(ServerError::InvalidRules)?;
+
+    if let Err(e) = validate_fleet(&ships, &rules) {
+        println!("Rejected placement: {} {}", &username, e);
+        return Err(RuleError::Placement(e).into());
+    }
+
     let state = &mut wrapper.shared.state.write().unwrap();
-    state.queue.push_back((username.clone(), sender, ships));
+    state.queue.push_back(QueueEntry {
+        client_id: username.clone(),
+        sender,
+        ships,
+        rules,
+        queued_at: Instant::now(),
+    });
 
-    return WsEvent::QueueRs {
+    Ok(WsEvent::QueueRs {
         player_id: username,
-    };
+    })
 }
 
 pub async fn match_players(wrapper: Wrapper) {
     {
         let state = &mut wrapper.shared.state.read().unwrap();
         // println!("Queue len: {}", state.queue.len());
-        if state.queue.len() < 2 {
+        if state.queue.is_empty() {
             return;
         }
     }
 
+    let bot_wait = wrapper.shared.config.queue_bot_wait;
     let (p1, p2);
     {
         let state = &mut wrapper.shared.state.write().unwrap();
-        p1 = state.queue.pop_front();
-        p2 = state.queue.pop_front();
+        // only players who asked for the same rules can be paired
+        let pair = (0..state.queue.len()).find_map(|i| {
+            (i + 1..state.queue.len())
+                .find(|&j| state.queue[i].rules == state.queue[j].rules)
+                .map(|j| (i, j))
+        });
+        if let Some((i, j)) = pair {
+            p2 = state.queue.remove(j);
+            p1 = state.queue.remove(i);
+        } else {
+            // nobody to pair with for too long, the bot steps in
+            let waited = state
+                .queue
+                .iter()
+                .position(|e| e.queued_at.elapsed() >= bot_wait);
+            p1 = waited.and_then(|i| state.queue.remove(i));
+            p2 = None;
+        }
     }
 
     //assume queue doesn't contain dangling players (removed on disconnect)
-    if let (Some((c1, sender1, ships1)), Some((c2, sender2, ships2))) = (p1, p2) {
-        let rs = game_new(
-            wrapper.clone(),
-            CreateGameRequest {
-                username: c1.clone(),
-                ships: ships1,
-            },
-        );
-        if let WsEvent::CreateGameRs { game_id, .. } = rs {
-            game_join(
-                wrapper.clone(),
-                JoinGameRequest {
+    let (e1, e2) = match (p1, p2) {
+        (Some(e1), Some(e2)) => (e1, e2),
+        (Some(entry), None) => return match_bot(wrapper, entry).await,
+        _ => return,
+    };
+    let players = [
+        Client::new(e1.client_id.clone(), e1.sender.clone()),
+        Client::new(e2.client_id.clone(), e2.sender.clone()),
+    ];
+    if let Err(e) = match_humans(wrapper, e1, e2).await {
+        println!("Matching failed: {}", e);
+        for player in players {
+            player.send(e.clone().into()).await;
+        }
+    }
+}
+
+async fn match_humans(wrapper: Wrapper, e1: QueueEntry, e2: QueueEntry) -> Result<(), ServerError> {
+    let (c1, sender1) = (e1.client_id, e1.sender);
+    let (c2, sender2) = (e2.client_id, e2.sender);
+    let rs = game_new(
+        wrapper.clone(),
+        CreateGameRequest {
+            username: c1.clone(),
+            ships: e1.ships,
+            rules: e1.rules,
+        },
+    )?;
+    let WsEvent::CreateGameRs { game_id, .. } = rs else {
+        return Err(ServerError::Internal("game not created".to_string()));
+    };
+    game_join(
+        wrapper.clone(),
+        JoinGameRequest {
+            game_id: game_id.clone(),
+            username: c2.clone(),
+            ships: e2.ships,
+        },
+    )?;
+
+    wrapper.attach_client(&game_id, Client::new(c1.clone(), sender1));
+
+    wrapper.attach_client(&game_id, Client::new(c2.clone(), sender2));
+
+    let (me, opponent) = wrapper.get_clients(&game_id)?;
+    let my_state = game_state(
+        wrapper.clone(),
+        StateRequest::new(game_id.clone(), me.id.clone()),
+    );
+    let opponent_state = game_state(
+        wrapper.clone(),
+        StateRequest::new(game_id.clone(), opponent.id.clone()),
+    );
+
+    println!("Matched {} vs {} in game {}", &c1, &c2, &game_id);
+
+    me.send(WsEvent::MatchedRs {
+        game_id: game_id.clone(),
+        opponent_id: opponent.id.clone(),
+        bot: false,
+    })
+    .await;
+    opponent
+        .send(WsEvent::MatchedRs {
+            game_id: game_id.clone(),
+            opponent_id: me.id.clone(),
+            bot: false,
+        })
+        .await;
+    me.send(WsEvent::GameStart {
+        game_id: game_id.clone(),
+    })
+    .await;
+    opponent
+        .send(WsEvent::GameStart {
+            game_id: game_id.clone(),
+        })
+        .await;
+
+    me.send(my_state).await;
+    opponent.send(opponent_state).await;
+    Ok(())
+}
+
+/// Seats a player who waited too long in the queue against the bot
+async fn match_bot(
+    wrapper: Wrapper,
+    QueueEntry {
+        client_id,
+        sender,
+        ships,
+        rules,
+        ..
+    }: QueueEntry,
+) {
+    let level = bot_level(&wrapper, &client_id);
+    println!("Bot level for {}: {:?}", &client_id, level);
+
+    let player = Client::new(client_id.clone(), sender);
+    let rs = game_vs_bot(
+        wrapper.clone(),
+        PlayBotRequest {
+            username: client_id.clone(),
+            ships,
+            rules,
+            level,
+        },
+    );
+    let game_id = match rs {
+        Ok(WsEvent::CreateGameRs { game_id, .. }) => game_id,
+        Ok(_) => return,
+        Err(e) => {
+            player.send(e.into()).await;
+            return;
+        }
+    };
+    wrapper.attach_client(&game_id, player.clone());
+
+    let (me, opponent) = match wrapper.get_clients(&game_id) {
+        Ok(clients) => clients,
+        Err(e) => {
+            player.send(e.into()).await;
+            return;
+        }
+    };
+    let bot_id = if me.id == client_id {
+        opponent.id
+    } else {
+        me.id
+    };
+    println!("Matched {} vs {} in game {}", &client_id, &bot_id, &game_id);
+
+    player
+        .send(WsEvent::MatchedRs {
+            game_id: game_id.clone(),
+            opponent_id: bot_id,
+            bot: true,
+        })
+        .await;
+    player
+        .send(WsEvent::GameStart {
+            game_id: game_id.clone(),
+        })
+        .await;
+    notify_players(wrapper, &game_id).await;
+}
+
+/// Bot strength for the player's record, records are kept under the resume token's id
+fn bot_level(wrapper: &Wrapper, client_id: &str) -> BotLevel {
+    let record = wrapper
+        .shared
+        .storage
+        .player(client_id)
+        .unwrap_or_else(|e| {
+            println!("Storage: player {} not loaded: {}", client_id, e);
+            None
+        });
+    BotLevel::matched(record.as_ref())
+}
+
+/// Sends fresh state to both players, followed by game over when the game has ended
+pub async fn notify_players(wrapper: Wrapper, game_id: &str) {
+    // get senders for me & opponent, nobody to tell once the game is closed
+    let Ok((me, opponent)) = wrapper.get_clients(game_id) else {
+        return;
+    };
+    let my_state = game_state(
+        wrapper.clone(),
+        StateRequest::new(game_id.to_string(), me.id.clone()),
+    );
+    let opponent_state = game_state(
+        wrapper.clone(),
+        StateRequest::new(game_id.to_string(), opponent.id.clone()),
+    );
+    me.send(my_state).await;
+    opponent.send(opponent_state).await;
+    broadcast_state(&wrapper, game_id);
+
+    if let Some(result) = wrapper.get_result(game_id) {
+        println!("Game over: {} {:?}", game_id, &result);
+
+        me.send(WsEvent::GameOver(result.clone())).await;
+        wrapper.broadcast(game_id, WsEvent::GameOver(result.clone()));
+        opponent.send(WsEvent::GameOver(result)).await;
+        // sockets stay open for a rematch, clients leave by closing them
+
+        match advance_series(wrapper.clone(), game_id) {
+            Some(WsEvent::SeriesOver(score)) => {
+                println!("Series over: {} {:?}", game_id, &score);
+                me.send(WsEvent::SeriesOver(score.clone())).await;
+                wrapper.broadcast(game_id, WsEvent::SeriesOver(score.clone()));
+                opponent.send(WsEvent::SeriesOver(score)).await;
+            }
+            Some(start) => {
+                let my_state = game_state(
+                    wrapper.clone(),
+                    StateRequest::new(game_id.to_string(), me.id.clone()),
+                );
+                let opponent_state = game_state(
+                    wrapper.clone(),
+                    StateRequest::new(game_id.to_string(), opponent.id.clone()),
+                );
+                wrapper.broadcast(game_id, start.clone());
+                let WsEvent::GameStart { game_id } = start else {
+                    return;
+                };
+                me.send(WsEvent::GameStart {
                     game_id: game_id.clone(),
-                    username: c2.clone(),
-                    ships: ships2,
-                },
-            );
+                })
+                .await;
+                opponent
+                    .send(WsEvent::GameStart {
+                        game_id: game_id.clone(),
+                    })
+                    .await;
+                me.send(my_state).await;
+                opponent.send(opponent_state).await;
+                broadcast_state(&wrapper, &game_id);
+            }
+            None => {}
+        }
+    }
+}
+
+/// Pushes the fogged state to the game's spectators
+fn broadcast_state(wrapper: &Wrapper, game_id: &str) {
+    let event = {
+        let state = wrapper.shared.state.read().unwrap();
+        let Some(game) = state.games.get(game_id) else {
+            return;
+        };
+        WsEvent::SpectateRs(grid_response(game, None))
+    };
+    wrapper.broadcast(game_id, event);
+}
 
-            wrapper.attach_client(&game_id, Client::new(c1.clone(), sender1));
+/// After a finished round of a series either starts the next one (`GameStart`)
+/// or reports the decided series (`SeriesOver`). None for single games.
+pub fn advance_series(wrapper: Wrapper, game_id: &str) -> Option<WsEvent> {
+    let mut state = wrapper.shared.state.write().unwrap();
+    let game = state.games.get_mut(game_id)?;
+    let score = game.core.series_score()?;
+    if score.winner.is_some() {
+        return Some(WsEvent::SeriesOver(score));
+    }
 
-            wrapper.attach_client(&game_id, Client::new(c2.clone(), sender2));
+    // nobody starts a round for a player who is gone
+    if !game.disconnected.is_empty() {
+        return None;
+    }
+    if game
+        .apply(Action::NextRound, &wrapper.shared.writer)
+        .ok()?
+        .is_empty()
+    {
+        return None;
+    }
+    if game.core.rules.is_timed() {
+        start_game_clock(wrapper.clone(), game_id.to_string());
+    }
+
+    Some(WsEvent::GameStart {
+        game_id: game_id.to_string(),
+    })
+}
 
-            let (me, opponent) = wrapper.get_clients(&game_id);
-            let my_state = game_state(wrapper.clone(), StateRequest::new(game_id.clone(), me.id));
-            let opponent_state = game_state(
+/// Brings back the games storage kept over a restart. Their players count as
+/// disconnected: they resume within `restore_grace` or forfeit, the bot plays on.
+/// Games that were not running are closed, as a disconnect would close them.
+pub async fn restore_games(wrapper: Wrapper) {
+    let saved = match wrapper.shared.storage.load_games() {
+        Ok(saved) => saved,
+        Err(e) => {
+            println!("Storage: games not restored: {}", e);
+            return;
+        }
+    };
+
+    for saved in saved {
+        let game = Game::restore(saved);
+        let game_id = game.id.clone();
+        if game.core.status != Progress {
+            println!("Closed restored game {} {:?}", &game_id, game.core.status);
+            wrapper
+                .shared
+                .writer
+                .send(Write::Close(game_id, game.notation()));
+            continue;
+        }
+        let players: Vec<ClientId> = [game.core.p1.as_ref(), game.core.p2.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(|p| p.name.clone())
+            .collect();
+        let bot = game.bot.clone();
+        let timed = game.core.rules.is_timed();
+        println!("Restored game {}", &game_id);
+        {
+            let state = &mut wrapper.shared.state.write().unwrap();
+            state.games.insert(game_id.clone(), game);
+            state.game_clients.insert(
+                game_id.clone(),
+                GameClients(
+                    players.first().cloned().unwrap_or_default(),
+                    players.get(1).cloned().unwrap_or_default(),
+                ),
+            );
+            for player in players.iter() {
+                state.client_games.insert(player.clone(), game_id.clone());
+            }
+        }
+
+        let mut bot_client = None;
+        for player in players.iter() {
+            let client = match &bot {
+                Some((bot_id, level)) if bot_id == player => {
+                    let client =
+                        bot::spawn(wrapper.clone(), game_id.clone(), bot_id.clone(), *level);
+                    bot_client = Some(client.clone());
+                    client
+                }
+                // nobody listens until the player resumes with a new socket
+                _ => Client::new(player.clone(), mpsc::channel(1).0),
+            };
+            wrapper.attach_client(&game_id, client);
+        }
+
+        for player in players
+            .iter()
+            .filter(|p| bot.as_ref().is_none_or(|(b, _)| b != *p))
+        {
+            let grace = wrapper.shared.config.restore_grace;
+            seat_left(wrapper.clone(), player, grace).await;
+        }
+        if timed {
+            start_game_clock(wrapper.clone(), game_id.clone());
+        }
+        // the bot may be the one to move
+        if let Some(bot_client) = bot_client {
+            let state = game_state(
                 wrapper.clone(),
-                StateRequest::new(game_id.clone(), opponent.id),
+                StateRequest::new(game_id.clone(), bot_client.id.clone()),
             );
+            bot_client.send(state).await;
+        }
+    }
+}
 
-            println!("Matched {} vs {} in game {}", &c1, &c2, &game_id);
+/// Handles a closed socket. Mid-game the seat is held for the grace period and the game
+/// ends as a forfeit if the player does not come back, other games are closed right away.
+pub async fn client_left(wrapper: Wrapper, client_id: &str) {
+    let grace = wrapper.shared.config.disconnect_grace;
+    seat_left(wrapper, client_id, grace).await;
+}
 
-            let _ = me
-                .sender
-                .send(WsEvent::GameStart {
-                    game_id: game_id.clone(),
-                })
-                .await
-                .unwrap();
-            let _ = opponent
-                .sender
-                .send(WsEvent::GameStart {
-                    game_id: game_id.clone(),
-                })
-                .await
-                .unwrap();
+async fn seat_left(wrapper: Wrapper, client_id: &str, grace: Duration) {
+    let (game_id, status, since, opponent) = {
+        let mut state = wrapper.shared.state.write().unwrap();
+        if let Some(idx) = state.queue.iter().position(|p| p.client_id == client_id) {
+            state.queue.remove(idx);
+        }
+        state.bots.remove(client_id);
+        state.players.remove(client_id);
 
-            let _ = me.sender.send(my_state).await.unwrap();
-            let _ = opponent.sender.send(opponent_state).await.unwrap();
+        let Some(game_id) = state.client_games.get(client_id).cloned() else {
+            return;
+        };
+        let opponent_id = state
+            .game_clients
+            .get(&game_id)
+            .map(|clients| clients.opponent(client_id).clone());
+        let Some(game) = state.games.get_mut(&game_id) else {
+            return;
         };
+        let since = Instant::now();
+        if game.core.status == Progress {
+            game.disconnected.insert(client_id.to_string(), since);
+        }
+        let opponent = [&game.client1, &game.client2]
+            .into_iter()
+            .flatten()
+            .find(|c| Some(&c.id) == opponent_id.as_ref())
+            .cloned();
+        (game_id, game.core.status, since, opponent)
     };
+    println!("client_left: {} {} {:?}", &game_id, client_id, status);
+
+    let reconnect_ms = (status == Progress).then_some(grace.as_millis() as u64);
+    if let Some(opponent) = &opponent {
+        opponent
+            .send(WsEvent::OpponentDisconnected {
+                player_id: client_id.to_string(),
+                reconnect_ms,
+            })
+            .await;
+    }
+
+    if status != Progress {
+        close_game(wrapper, &game_id, client_id).await;
+        return;
+    }
+
+    let client_id = client_id.to_string();
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        let forfeit = {
+            let mut state = wrapper.shared.state.write().unwrap();
+            let Some(game) = state.games.get_mut(&game_id) else {
+                return;
+            };
+            // came back in time, a later drop has its own timer
+            if game.disconnected.get(&client_id) != Some(&since) {
+                return;
+            }
+            let abandon = Action::Abandon {
+                player: client_id.clone(),
+            };
+            game.apply(abandon, &wrapper.shared.writer)
+                .is_ok_and(|events| !events.is_empty())
+        };
+
+        if forfeit {
+            println!("disconnect_forfeit: {} {}", &game_id, &client_id);
+            notify_players(wrapper.clone(), &game_id).await;
+        }
+        close_game(wrapper, &game_id, &client_id).await;
+    });
+}
+
+/// Removes the game and closes the socket of whoever is still connected
+async fn close_game(wrapper: Wrapper, game_id: &str, left: &str) {
+    for client in wrapper.remove_game(game_id) {
+        if client.id != left {
+            client.send(WsEvent::Disconnect).await;
+        }
+    }
+}
+
+/// One task per round of a timed game, sleeps until the next turn/clock deadline and applies
+/// the timeout rules. A rematch or next round starts a fresh task and this one exits.
+pub fn start_game_clock(wrapper: Wrapper, game_id: GameId) {
+    tokio::spawn(async move {
+        // callers may still hold the state lock, read the round once the task runs
+        let Some(round) = wrapper.round(&game_id) else {
+            return;
+        };
+        loop {
+            let deadline = {
+                let state = wrapper.shared.state.read().unwrap();
+                match state.games.get(&game_id) {
+                    Some(g) if g.core.status == Progress && g.core.round == round => {
+                        g.core.next_deadline()
+                    }
+                    _ => return, //finished, removed on disconnect or a later round
+                }
+            };
+
+            match deadline {
+                Some(d) if d > Instant::now() => {
+                    tokio::time::sleep_until(tokio::time::Instant::from_std(d)).await;
+                }
+                Some(_) => {
+                    if check_clocks(wrapper.clone(), &game_id) {
+                        notify_players(wrapper.clone(), &game_id).await;
+                    }
+                }
+                None => return,
+            }
+        }
+    });
+}
+
+/// Applies flag fall or the turn timeout policy once a deadline has passed, true if the game changed
+pub fn check_clocks(wrapper: Wrapper, game_id: &str) -> bool {
+    let mut state = wrapper.shared.state.write().unwrap();
+    let Some(game) = state.games.get_mut(game_id) else {
+        return false;
+    };
+
+    let idle = game.core.current_turn.clone();
+    let events = game
+        .apply(Action::Timeout, &wrapper.shared.writer)
+        .unwrap_or_default();
+    if events.is_empty() {
+        return false;
+    }
+    println!("timeout: {} {} {:?}", game_id, &idle, &events);
+    true
 }
 
 pub fn game_turn(
@@ -194,125 +759,279 @@ pub fn game_turn(
         x,
         y,
     }: TurnRequest,
-) -> WsEvent {
+) -> Result<WsEvent, ServerError> {
     println!("game_turn: {} {} {}:{}", &game_id, &username, x, y);
 
-    if x < 0 && x > 9 || y < 0 || y > 9 {
-        return WsEvent::BadRequestRs("Incorrect coordinates".to_string());
+    {
+        let mut state = wrapper.shared.state.write().unwrap();
+        let game = state
+            .games
+            .get_mut(&game_id)
+            .ok_or_else(|| ServerError::NoSuchGame(game_id.clone()))?;
+
+        let shot = Action::Shoot {
+            player: username.clone(),
+            at: Point2d::new(x, y),
+        };
+        game.apply(shot, &wrapper.shared.writer)?;
     }
 
-    {
-        let state = wrapper.shared.state.read().unwrap();
-        let game = state.games.get(&game_id).unwrap();
+    Ok(game_state(
+        wrapper.clone(),
+        StateRequest::new(game_id, username),
+    ))
+}
 
-        if game.status == WaitingPlayers || game.status == GameOver {
-            return WsEvent::TurnRs(GridDTO {
-                me: vec![],
-                enemy: vec![],
-            });
-        }
+pub fn game_salvo(
+    wrapper: Wrapper,
+    SalvoRequest {
+        game_id,
+        username,
+        shots,
+    }: SalvoRequest,
+) -> Result<WsEvent, ServerError> {
+    println!("game_salvo: {} {} {:?}", &game_id, &username, &shots);
 
-        let is_turning_player = username == game.current_turn; //bug always p2 turn if no such name
-        if !is_turning_player {
-            return game_state(wrapper.clone(), StateRequest::new(game_id, username));
-        }
-    }
+    let mut state = wrapper.shared.state.write().unwrap();
+    let game = state
+        .games
+        .get_mut(&game_id)
+        .ok_or_else(|| ServerError::NoSuchGame(game_id.clone()))?;
+    let salvo = Action::Salvo {
+        player: username,
+        shots: shots.into_iter().map(|(x, y)| Point2d::new(x, y)).collect(),
+    };
+    let events = game.apply(salvo, &wrapper.shared.writer)?;
 
-    {
-        let mut state = wrapper.shared.state.write().unwrap();
-        let game = state.games.get_mut(&game_id).unwrap();
+    let results = events
+        .into_iter()
+        .filter_map(|e| match e {
+            GameEvent::Shot { outcome, .. } => Some(outcome),
+            _ => None,
+        })
+        .collect();
+    Ok(WsEvent::SalvoRs { results })
+}
+
+pub fn game_resign(
+    wrapper: Wrapper,
+    ResignRequest { game_id, username }: ResignRequest,
+) -> Result<WsEvent, ServerError> {
+    println!("game_resign: {} {}", &game_id, &username);
 
-        do_turn_user(Point2d { x, y }, username.clone(), game);
+    let mut state = wrapper.shared.state.write().unwrap();
+    let game = state
+        .games
+        .get_mut(&game_id)
+        .ok_or_else(|| ServerError::NoSuchGame(game_id.clone()))?;
+
+    let resign = Action::Resign { player: username };
+    let result = game
+        .apply(resign, &wrapper.shared.writer)?
+        .into_iter()
+        .find_map(|e| match e {
+            GameEvent::GameOver(result) => Some(result),
+            _ => None,
+        })
+        .ok_or_else(|| ServerError::Internal("resignation did not end the game".to_string()))?;
+    Ok(WsEvent::GameOver(result))
+}
+
+pub fn game_rematch(
+    wrapper: Wrapper,
+    RematchRequest {
+        game_id,
+        username,
+        ships,
+    }: RematchRequest,
+) -> Result<WsEvent, ServerError> {
+    println!("game_rematch: {} {}", &game_id, &username);
+
+    let mut state = wrapper.shared.state.write().unwrap();
+    let game = state
+        .games
+        .get_mut(&game_id)
+        .ok_or_else(|| ServerError::NoSuchGame(game_id.clone()))?;
+
+    let rematch = Action::Rematch {
+        player: username.clone(),
+        ships,
+    };
+    let started = game
+        .apply(rematch, &wrapper.shared.writer)?
+        .iter()
+        .any(|e| matches!(e, GameEvent::Started { .. }));
+
+    if started && game.core.rules.is_timed() {
+        start_game_clock(wrapper.clone(), game_id.clone());
     }
 
-    return game_state(wrapper.clone(), StateRequest::new(game_id, username));
+    Ok(WsEvent::RematchRs {
+        game_id,
+        player_id: username,
+        round: game.core.round,
+        started,
+    })
 }
 
 pub fn game_state(wrapper: Wrapper, StateRequest { game_id, username }: StateRequest) -> WsEvent {
     // println!("game_state: {} {}", &game_id, &owner);
 
     let state = wrapper.shared.state.read().unwrap();
-    if let None = state.games.get(&game_id) {
+    let Some(game) = state.games.get(&game_id) else {
         return WsEvent::StateRs(GridResponse {
             status: GameOver,
             action: None,
+            shots: None,
+            time_left_ms: None,
+            clocks: HashMap::new(),
+            result: None,
+            series: None,
             me: None,
             enemy: None,
             grid: HashMap::new(),
         });
+    };
+    WsEvent::StateRs(grid_response(game, Some(&username)))
+}
+
+pub fn game_chat(
+    wrapper: Wrapper,
+    ChatRequest {
+        game_id,
+        username,
+        text,
+    }: ChatRequest,
+    spectator: bool,
+) -> Result<WsEvent, ServerError> {
+    let state = wrapper.shared.state.read().unwrap();
+    let Some(game) = state.games.get(&game_id) else {
+        return Err(ServerError::NoSuchGame(game_id));
+    };
+    if spectator && !game.core.rules.spectator_chat {
+        return Err(ServerError::ChatRejected(
+            "Spectators can't write to this chat".to_string(),
+        ));
+    }
+    if !spectator && game.core.player(&username).is_none() {
+        return Err(RuleError::NotAPlayer.into());
+    }
+
+    let text = text.trim();
+    if text.is_empty() {
+        return Err(ServerError::ChatRejected("Empty chat message".to_string()));
+    }
+    if text.chars().count() > MAX_CHAT_LEN {
+        return Err(ServerError::ChatRejected(format!(
+            "Chat message is limited to {} characters",
+            MAX_CHAT_LEN
+        )));
+    }
+
+    let sent_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default();
+    Ok(WsEvent::ChatMsg {
+        game_id,
+        sender: username,
+        text: text.to_string(),
+        sent_at,
+    })
+}
+
+/// Delivers a chat message to the players and everyone in the room
+pub async fn send_chat(wrapper: Wrapper, game_id: &str, msg: WsEvent) {
+    let clients: Vec<Client> = {
+        let state = wrapper.shared.state.read().unwrap();
+        let Some(game) = state.games.get(game_id) else {
+            return;
+        };
+        [&game.client1, &game.client2]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect()
+    };
+
+    wrapper.broadcast(game_id, msg.clone());
+    for client in clients {
+        client.send(msg.clone()).await;
     }
+}
 
-    let game = state.games.get(&game_id).unwrap();
+/// Snapshot of a game for a spectator, fleets are hidden except what was shot
+pub fn game_spectate(
+    wrapper: Wrapper,
+    SpectateRequest { game_id }: SpectateRequest,
+) -> Result<WsEvent, ServerError> {
+    println!("game_spectate: {}", &game_id);
 
+    let state = wrapper.shared.state.read().unwrap();
+    match state.games.get(&game_id) {
+        Some(game) => Ok(WsEvent::SpectateRs(grid_response(game, None))),
+        None => Err(ServerError::NoSuchGame(game_id)),
+    }
+}
+
+/// Game state as seen by `viewer`, spectators (None) see both fleets fogged like an enemy's
+fn grid_response(game: &Game, viewer: Option<&str>) -> GridResponse {
+    let game = &game.core;
     if game.status == WaitingPlayers {
-        return WsEvent::StateRs(GridResponse::new(game.status.clone(), None));
+        return GridResponse::new(game.status, None);
     }
 
-    let is_turning_player = username == game.current_turn;
-    let action = if is_turning_player {
-        PlayerAction::Shoot
-    } else {
-        PlayerAction::Wait
-    }; //bug always p2 turn if no such name
+    // only a seated player has a move to make
+    let action = viewer
+        .filter(|name| game.player(name).is_some())
+        .map(|name| {
+            if name == game.current_turn {
+                PlayerAction::Shoot
+            } else {
+                PlayerAction::Wait
+            }
+        });
+
+    let shots = match game.rules.mode {
+        GameMode::Classic => None,
+        GameMode::Salvo => game.player(&game.current_turn).map(|p| p.alive_ships()),
+    };
 
-    let players = vec![game.p1.as_ref().unwrap(), game.p2.as_ref().unwrap()];
+    let players: Vec<&Player> = [game.p1.as_ref(), game.p2.as_ref()]
+        .into_iter()
+        .flatten()
+        .collect();
     let mut players_grid = HashMap::new();
-    for p in players {
-        players_grid.insert(p.name.clone(), grid_as_json_single(p, username != p.name));
+    for p in players.iter().copied() {
+        players_grid.insert(
+            p.name.clone(),
+            grid_as_json_single(p, viewer != Some(p.name.as_str())),
+        );
     }
 
-    return WsEvent::StateRs(GridResponse {
-        status: game.status.clone(),
-        action: Some(action),
-        me: None,
-        enemy: None,
-        grid: players_grid,
-    });
-}
+    let time_left_ms = game
+        .turn_deadline
+        .map(|d| d.saturating_duration_since(Instant::now()).as_millis() as u64);
 
-pub fn do_turn_user(hit: Point2d, requester: String, game: &mut Game) -> GameFlow {
-    let players = vec![game.p1.as_mut().unwrap(), game.p2.as_mut().unwrap()];
-    let mut enemy_opt = None;
+    let mut clocks = HashMap::new();
     for p in players {
-        if p.name != requester {
-            enemy_opt = Some(p);
-            break;
+        if let Some(left) = game.clock_left(&p.name) {
+            clocks.insert(p.name.clone(), left.as_millis() as u64);
         }
     }
 
-    let enemy = enemy_opt.unwrap();
-    match enemy.grid_state[hit.x][hit.y] {
-        CellType::EmptyNoShip => {
-            enemy.grid_state[hit.x][hit.y] = CellType::EmptyMissed;
-            game.current_turn = enemy.name.clone();
-        } //miss
-        CellType::HasShip => {
-            let s = enemy
-                .ship_health
-                .get_mut(&Point2d { x: hit.x, y: hit.y })
-                .unwrap();
-            let mark_as_hit_after_kill = s.lock().unwrap().hit();
-            enemy.grid_state[hit.x][hit.y] = CellType::HasShipHit;
-
-            for p in mark_as_hit_after_kill {
-                enemy.grid_state[p.x][p.y] = CellType::EmptyMissed;
-            }
-            //don't change current turn player
-        }
-        CellType::EmptyMissed => {} //already miss at prev turn, do nothing
-        CellType::HasShipHit => {}  //already hit at prev turn, do nothing
+    GridResponse {
+        status: game.status,
+        action,
+        shots,
+        time_left_ms,
+        clocks,
+        result: game.result.clone(),
+        series: game.series_score(),
+        me: None,
+        enemy: None,
+        grid: players_grid,
     }
-
-    return match enemy.is_all_destroyed() {
-        true => {
-            game.status = GameOver;
-            GameFlow::GameOver
-        }
-        false => {
-            game.status = Progress;
-            GameFlow::NextTurn
-        }
-    };
 }
 
 pub fn grid_as_json_single(p: &Player, enemy: bool) -> Grid2D {
@@ -324,18 +1043,23 @@ pub fn grid_as_json_single(p: &Player, enemy: bool) -> Grid2D {
                     CellType::HasShipHit => grid[x][y] = "x".to_string(),
                     CellType::HasShip => grid[x][y] = "#".to_string(),
                     CellType::EmptyMissed => grid[x][y] = "_".to_string(),
+                    CellType::AutoRevealed => grid[x][y] = "~".to_string(),
                 }
             }
         }
     };
 
-    let mut grid: Grid2D = vec![vec![String::new(); 10]; 10];
+    let mut grid: Grid2D = p
+        .grid_state
+        .iter()
+        .map(|row| vec![String::new(); row.len()])
+        .collect();
     draw_cell_types(p, &mut grid);
 
     if enemy {
         //hide enemy ships
-        for (_, row) in grid.iter_mut().enumerate() {
-            for (_, cell) in row.iter_mut().enumerate() {
+        for row in grid.iter_mut() {
+            for cell in row.iter_mut() {
                 *cell = if cell == "#" {
                     ".".to_string()
                 } else {
@@ -345,5 +1069,185 @@ pub fn grid_as_json_single(p: &Player, enemy: bool) -> Grid2D {
         }
     }
 
-    return grid;
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::dto::{GameOverReason, GameResult};
+    use crate::rules::tests::classic_fleet;
+    use crate::rules::RuleSet;
+    use crate::storage::{FinishedGame, MemoryStorage, SavedGame, Storage};
+    use std::sync::Arc;
+
+    fn server() -> Wrapper {
+        let config = ServerConfig {
+            game_seed: Some(1),
+            ..ServerConfig::default()
+        };
+        Wrapper::new(config, Arc::new(MemoryStorage::default()))
+    }
+
+    /// "a" and "b" seated in a new game, its id
+    fn started(wrapper: &Wrapper, rules: RuleSet) -> GameId {
+        let created = game_new(
+            wrapper.clone(),
+            CreateGameRequest {
+                username: "a".to_string(),
+                ships: classic_fleet(),
+                rules,
+            },
+        );
+        let Ok(WsEvent::CreateGameRs { game_id, .. }) = created else {
+            panic!("game not created: {:?}", created);
+        };
+        let join = JoinGameRequest {
+            game_id: game_id.clone(),
+            username: "b".to_string(),
+            ships: classic_fleet(),
+        };
+        game_join(wrapper.clone(), join).unwrap();
+        game_id
+    }
+
+    fn chat(
+        wrapper: &Wrapper,
+        game_id: &str,
+        username: &str,
+        text: &str,
+        spectator: bool,
+    ) -> Result<WsEvent, ServerError> {
+        let rq = ChatRequest {
+            game_id: game_id.to_string(),
+            username: username.to_string(),
+            text: text.to_string(),
+        };
+        game_chat(wrapper.clone(), rq, spectator)
+    }
+
+    #[test]
+    fn chat_text_is_trimmed_and_capped() {
+        let wrapper = server();
+        let game_id = started(&wrapper, RuleSet::classic());
+
+        let longest = "x".repeat(MAX_CHAT_LEN);
+        let sent = chat(&wrapper, &game_id, "a", &format!("  {}  ", longest), false);
+        assert!(matches!(sent, Ok(WsEvent::ChatMsg { text, .. }) if text == longest));
+
+        // characters, not bytes
+        let cyrillic = "ж".repeat(MAX_CHAT_LEN);
+        assert!(chat(&wrapper, &game_id, "a", &cyrillic, false).is_ok());
+
+        let too_long = "x".repeat(MAX_CHAT_LEN + 1);
+        let rejected = chat(&wrapper, &game_id, "a", &too_long, false);
+        assert!(matches!(rejected, Err(ServerError::ChatRejected(_))));
+        let blank = chat(&wrapper, &game_id, "a", "   ", false);
+        assert!(matches!(blank, Err(ServerError::ChatRejected(_))));
+    }
+
+    #[test]
+    fn spectators_write_only_where_the_rules_allow() {
+        let wrapper = server();
+        let closed = started(&wrapper, RuleSet::classic());
+        let rejected = chat(&wrapper, &closed, "watcher", "hi", true);
+        assert!(matches!(rejected, Err(ServerError::ChatRejected(_))));
+        // spectators can't pass for players either
+        let rejected = chat(&wrapper, &closed, "watcher", "hi", false);
+        assert_eq!(rejected, Err(RuleError::NotAPlayer.into()));
+
+        let open = RuleSet {
+            spectator_chat: true,
+            ..RuleSet::classic()
+        };
+        let wrapper = server();
+        let open = started(&wrapper, open);
+        assert!(chat(&wrapper, &open, "watcher", "hi", true).is_ok());
+    }
+
+    #[test]
+    fn strong_player_gets_the_hard_bot() {
+        let wrapper = server();
+        let storage = &wrapper.shared.storage;
+        for loser in ["b", "c", "d"] {
+            let finished = FinishedGame {
+                game_id: format!("vs-{}", loser),
+                round: 1,
+                finished: 0,
+                result: GameResult {
+                    winner: "a".to_string(),
+                    loser: loser.to_string(),
+                    reason: GameOverReason::Resignation,
+                    shots: HashMap::new(),
+                },
+            };
+            storage.record_result(&finished).unwrap();
+        }
+        assert_eq!(bot_level(&wrapper, "a"), BotLevel::Hard);
+        assert_eq!(bot_level(&wrapper, "b"), BotLevel::Medium);
+        assert_eq!(bot_level(&wrapper, "new"), BotLevel::Medium);
+    }
+
+    #[tokio::test]
+    async fn player_id_is_claimed_by_one_socket_at_a_time() {
+        let wrapper = server();
+        started(&wrapper, RuleSet::classic());
+        assert!(!wrapper.claim_player("a"), "seated");
+
+        assert!(wrapper.claim_player("c"));
+        assert!(!wrapper.claim_player("c"), "on another socket");
+        client_left(wrapper.clone(), "c").await;
+        assert!(wrapper.claim_player("c"));
+    }
+
+    /// A game of "a" and "b" saved the way it stood after `actions`
+    fn saved_game(seed: u64, actions: Vec<Action>) -> SavedGame {
+        let mut game = Game::new(RuleSet::classic(), seed);
+        for action in actions {
+            game.core.apply(action).unwrap();
+        }
+        game.saved()
+    }
+
+    fn join(player: &str) -> Action {
+        Action::Join {
+            player: player.to_string(),
+            ships: classic_fleet(),
+        }
+    }
+
+    #[tokio::test]
+    async fn only_running_games_are_restored() {
+        let storage = Arc::new(MemoryStorage::default());
+        let resign = Action::Resign {
+            player: "a".to_string(),
+        };
+        let waiting = saved_game(1, vec![join("a")]);
+        let running = saved_game(2, vec![join("a"), join("b")]);
+        let over = saved_game(3, vec![join("a"), join("b"), resign]);
+        for game in [&waiting, &running, &over] {
+            storage.save_game(game).unwrap();
+        }
+        let wrapper = Wrapper::new(ServerConfig::default(), storage.clone());
+
+        restore_games(wrapper.clone()).await;
+        {
+            let state = wrapper.shared.state.read().unwrap();
+            let restored: Vec<&GameId> = state.games.keys().collect();
+            assert_eq!(restored, vec![&running.id]);
+            let seated = state.client_games.get("a");
+            assert_eq!(seated, Some(&running.id));
+        }
+        // storage is written behind the state
+        for _ in 0..50 {
+            if storage.load_games().unwrap().len() == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(storage.load_games().unwrap(), vec![running]);
+        assert!(storage.replay(&over.id).unwrap().is_some());
+        assert_eq!(storage.replay(&waiting.id).unwrap(), None);
+    }
 }
@@ -1,18 +1,24 @@
-use std::collections::{HashMap, VecDeque};
 use std::env;
 use std::net::SocketAddr;
 
-use crate::app_state::{Client, MyState, Shared, Wrapper};
-use crate::dto::{StateRequest, WsEvent};
+use crate::app_state::{Client, Wrapper};
+use crate::chat::ChatLimiter;
+use crate::config::ServerConfig;
+use crate::dto::{ClientId, GameId, StateRequest, WsEvent};
+use crate::error::ServerError;
+use crate::storage::{MemoryStorage, SqliteStorage, Storage};
+use crate::tournament::TournamentRequest;
 use axum::extract::ws::{Message, WebSocket};
-use axum::extract::{State, WebSocketUpgrade};
+use axum::extract::{Path, State, WebSocketUpgrade};
+use axum::http::{header, StatusCode};
 use axum::response::{Html, IntoResponse};
 use axum::routing::get;
-use axum::Router;
+use axum::{Json, Router};
 use futures::{sink::SinkExt, stream::StreamExt};
 use serde_json::json;
-use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Receiver;
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
@@ -24,11 +30,35 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
 
 mod app_state;
+mod bot;
+mod chat;
+mod config;
 mod dto;
+mod engine;
+mod error;
 mod game_engine;
+mod notation;
+mod rules;
+mod session;
+mod simulate;
+mod storage;
+mod tournament;
+
+/// Rounds listed by `/player/{id}/results`
+const RESULTS_SHOWN: usize = 20;
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).is_some_and(|a| a == "simulate") {
+        simulate::run(&args[2..]);
+        return;
+    }
+    if args.get(1).is_some_and(|a| a == "replay") {
+        notation::run(&args[2..]);
+        return;
+    }
+
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| {
@@ -42,16 +72,19 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let app_state = Wrapper {
-        shared: Arc::new(Shared {
-            state: RwLock::new(MyState {
-                games: HashMap::new(),
-                client_games: HashMap::new(),
-                game_clients: HashMap::new(),
-                queue: VecDeque::with_capacity(100),
-            }),
-        }),
+    let config = ServerConfig::from_env();
+    let storage: Arc<dyn Storage> = match &config.database {
+        Some(path) => match SqliteStorage::open(path) {
+            Ok(storage) => Arc::new(storage),
+            Err(e) => {
+                println!("Cannot open database {}: {}", path, e);
+                return;
+            }
+        },
+        None => Arc::new(MemoryStorage::default()),
     };
+    let app_state = Wrapper::new(config, storage);
+    game_engine::restore_games(app_state.clone()).await;
 
     start_matchmaker(app_state.clone());
 
@@ -62,6 +95,13 @@ async fn main() {
         )
         .nest_service("/static", ServeDir::new("static"))
         .route("/ws", get(ws_handler))
+        .route(
+            "/tournament",
+            get(tournament_standings).post(tournament_start),
+        )
+        .route("/replay/{game_id}", get(replay_download))
+        .route("/player/{player_id}", get(player_record))
+        .route("/player/{player_id}/results", get(player_results))
         .with_state(app_state)
         .layer(
             TraceLayer::new_for_http()
@@ -84,6 +124,71 @@ async fn ws_handler(ws: WebSocketUpgrade, State(wrapper): State<Wrapper>) -> imp
     ws.on_upgrade(|socket| websocket(socket, wrapper))
 }
 
+/// Plain text standings of the running or last tournament
+async fn tournament_standings(State(wrapper): State<Wrapper>) -> impl IntoResponse {
+    match tournament::standings(&wrapper) {
+        Some(standings) => (StatusCode::OK, standings.to_string()),
+        None => (StatusCode::NOT_FOUND, "No tournament yet".to_string()),
+    }
+}
+
+/// Notation of a finished game as a text file, 404 while it is still played
+async fn replay_download(
+    State(wrapper): State<Wrapper>,
+    Path(game_id): Path<GameId>,
+) -> impl IntoResponse {
+    match wrapper.replay(&game_id) {
+        Some(text) => {
+            let file = format!("attachment; filename=\"{}.txt\"", game_id);
+            (StatusCode::OK, [(header::CONTENT_DISPOSITION, file)], text).into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "No such finished game").into_response(),
+    }
+}
+
+/// Games played, won and lost, counted per round
+async fn player_record(
+    State(wrapper): State<Wrapper>,
+    Path(player_id): Path<ClientId>,
+) -> impl IntoResponse {
+    match wrapper.shared.storage.player(&player_id) {
+        Ok(Some(record)) => Json(record).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "No such player").into_response(),
+        Err(e) => {
+            println!("Storage: player {} not loaded: {}", player_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The player's last decided rounds, newest first
+async fn player_results(
+    State(wrapper): State<Wrapper>,
+    Path(player_id): Path<ClientId>,
+) -> impl IntoResponse {
+    match wrapper.shared.storage.results(&player_id, RESULTS_SHOWN) {
+        Ok(results) => Json(results).into_response(),
+        Err(e) => {
+            println!("Storage: results of {} not loaded: {}", player_id, e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn tournament_start(
+    State(wrapper): State<Wrapper>,
+    Json(rq): Json<TournamentRequest>,
+) -> impl IntoResponse {
+    let admin_token = wrapper.shared.config.admin_token.as_ref();
+    if admin_token.is_none_or(|token| *token != rq.admin_token) {
+        return (StatusCode::FORBIDDEN, "Wrong admin token".to_string());
+    }
+    match tournament::start(wrapper, rq) {
+        Ok(standings) => (StatusCode::ACCEPTED, standings.to_string()),
+        Err(e) => (StatusCode::BAD_REQUEST, e),
+    }
+}
+
 async fn websocket(stream: WebSocket, wrapper: Wrapper) {
     let connection_id = Uuid::new_v4().to_string();
     println!("Client connected: {}", connection_id);
@@ -113,89 +218,22 @@ async fn websocket(stream: WebSocket, wrapper: Wrapper) {
     });
 
     //not async, before spawing async loops
-    // let mut broadband_handle = None;
+    let mut session = Session {
+        connection_id,
+        sender: self_chan_sender,
+        spectating: None,
+        broadband_handle: None,
+        chat_limiter: ChatLimiter::default(),
+        is_bot: false,
+    };
     while let Some(Ok(msg)) = self_ws_in.next().await {
         match msg {
             Message::Text(text) => {
                 println!("received: {}", text);
-                let v: WsEvent = serde_json::from_str(text.as_str()).unwrap();
-                match v {
-                    WsEvent::ConnectRq { .. } => {
-                        let _ = self_chan_sender
-                            .send(WsEvent::ConnectRs {
-                                player_id: connection_id.clone(),
-                            })
-                            .await
-                            .unwrap();
-                    }
-                    WsEvent::CreateGameRq(mut rq) => {
-                        rq.username = connection_id.clone();
-
-                        let response = game_engine::game_new(wrapper.clone(), rq);
-                        let game_id = match &response {
-                            WsEvent::CreateGameRs { game_id, .. } => game_id.clone(),
-                            _ => String::new(),
-                        };
-
-                        // set up channels
-                        wrapper.attach_client(
-                            &game_id,
-                            Client::new(connection_id.clone(), self_chan_sender.clone()),
-                        );
-
-                        // let room_receiver = wrapper.get_room_sender(&game_id).subscribe();
-                        // broadband_handle = Some(broadband_consumer(room_receiver, self_chan_sender.clone()));
-
-                        let _ = self_chan_sender.send(response).await.unwrap();
-                        break;
-                    }
-                    WsEvent::JoinRq(mut rq) => {
-                        let username = connection_id.clone();
-                        let game_id = rq.game_id.clone();
-                        rq.username = username.clone();
-
-                        // set up channels
-                        wrapper.attach_client(
-                            &game_id,
-                            Client::new(username.clone(), self_chan_sender.clone()),
-                        );
-
-                        // let room_receiver = wrapper.get_room_sender(&game_id).subscribe();
-                        // broadband_handle = Some(broadband_consumer(room_receiver, self_chan_sender.clone()));
-
-                        let response = game_engine::game_join(wrapper.clone(), rq);
-                        let _ = self_chan_sender.send(response).await.unwrap();
-
-                        //this not delivered to p1... blocked fpr some reason and order break
-                        // let _ = wrapper.get_room_sender(&game_id).send(json!(WsEvent::GameStart).to_string()).unwrap();
-
-                        // get senders for me & opponent
-                        // send initial state for me & opponent
-                        let (me, opponent) = wrapper.get_clients(&game_id);
-                        let my_state = game_engine::game_state(
-                            wrapper.clone(),
-                            StateRequest::new(game_id.clone(), me.id),
-                        );
-                        let opponent_state = game_engine::game_state(
-                            wrapper.clone(),
-                            StateRequest::new(game_id.clone(), opponent.id),
-                        );
-                        let _ = me.sender.send(my_state).await.unwrap();
-                        let _ = opponent.sender.send(opponent_state).await.unwrap();
-
-                        break;
-                    }
-                    WsEvent::QueueRq(mut rq) => {
-                        let username = connection_id.clone();
-                        rq.username = username.clone();
-
-                        let response =
-                            game_engine::enqueue(wrapper.clone(), rq, self_chan_sender.clone());
-                        let _ = self_chan_sender.send(response).await.unwrap();
-
-                        break;
-                    }
-                    _ => {}
+                match lobby_event(wrapper.clone(), &mut session, text.as_str()).await {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => reply(&session.sender, e).await,
                 }
             }
             _ => {
@@ -205,60 +243,35 @@ async fn websocket(stream: WebSocket, wrapper: Wrapper) {
         }
     }
     //--end not async
+    if let Some(handle) = session.broadband_handle.take() {
+        handle.abort();
+    }
 
     //loop msg after joining... and use BREAK if needed!
-    let connection_id_copy = connection_id.clone();
+    let connection_id = session.connection_id.clone();
     let wrapper_copy = wrapper.clone();
     let mut recv_task = tokio::spawn(async move {
-        let connection_id = connection_id_copy;
+        let mut session = session;
         let wrapper = wrapper_copy;
         while let Some(Ok(msg)) = self_ws_in.next().await {
             match msg {
                 Message::Text(text) => {
                     println!("received: {}", text);
-                    let v: WsEvent = serde_json::from_str(text.as_str()).unwrap();
-                    match v {
-                        WsEvent::TurnRq(mut rq) => {
-                            let username = connection_id.clone();
-                            {
-                                if let None = wrapper
-                                    .shared
-                                    .state
-                                    .read()
-                                    .unwrap()
-                                    .client_games
-                                    .get(&username)
-                                {
-                                    break;
-                                }
-                            }
-                            let game_id = rq.game_id.clone();
-                            rq.username = connection_id.clone();
-                            game_engine::game_turn(wrapper.clone(), rq);
-
-                            // get senders for me & opponent
-                            // send initial state for me & opponent
-                            let (me, opponent) = wrapper.get_clients(&game_id);
-                            let my_state = game_engine::game_state(
-                                wrapper.clone(),
-                                StateRequest::new(game_id.clone(), me.id),
-                            );
-                            let opponent_state = game_engine::game_state(
-                                wrapper.clone(),
-                                StateRequest::new(game_id.clone(), opponent.id),
-                            );
-                            let _ = me.sender.send(my_state).await.unwrap();
-                            let _ = opponent.sender.send(opponent_state).await.unwrap();
-
-                            if wrapper.is_game_over(&game_id) {
-                                let _ = me.sender.send(WsEvent::GameOver).await.unwrap();
-                                let _ = opponent.sender.send(WsEvent::GameOver).await.unwrap();
-
-                                let _ = me.sender.send(WsEvent::Disconnect).await.unwrap();
-                                let _ = opponent.sender.send(WsEvent::Disconnect).await.unwrap();
-                            }
+                    {
+                        let in_game = wrapper
+                            .shared
+                            .state
+                            .read()
+                            .unwrap()
+                            .client_games
+                            .contains_key(&session.connection_id);
+                        // between tournament games a bot just waits
+                        if !in_game && !session.is_bot {
+                            break;
                         }
-                        _ => {}
+                    }
+                    if let Err(e) = game_event(wrapper.clone(), &mut session, text.as_str()).await {
+                        reply(&session.sender, e).await;
                     }
                 }
                 Message::Close(_) => {
@@ -282,16 +295,335 @@ async fn websocket(stream: WebSocket, wrapper: Wrapper) {
     }
 
     println!("Client disconnected: {}", &connection_id);
-    //handle disconnected
-    let state = &mut wrapper.shared.state.write().unwrap();
-    if let Some(game_id) = state.client_games.remove(&connection_id) {
-        state.game_clients.remove(&game_id);
-        state.games.remove(&game_id);
-
-        if let Some(idx) = state.queue.iter().position(|p| &p.0 == &connection_id) {
-            state.queue.remove(idx);
+    game_engine::client_left(wrapper, &connection_id).await;
+}
+
+/// What the socket loops know about their client
+struct Session {
+    connection_id: ClientId,
+    sender: Sender<WsEvent>,
+    /// Game watched from the lobby, see `SpectateRq`
+    spectating: Option<GameId>,
+    broadband_handle: Option<JoinHandle<()>>,
+    // keeps chat from flooding the socket channels of everyone in the room
+    chat_limiter: ChatLimiter,
+    /// Authenticated external bot, stays connected between tournament games
+    is_bot: bool,
+}
+
+/// Answers on the client's own channel, a closed one means the socket is gone already
+async fn reply(sender: &Sender<WsEvent>, event: impl Into<WsEvent>) {
+    let _ = sender.send(event.into()).await;
+}
+
+fn parse(text: &str) -> Result<WsEvent, ServerError> {
+    serde_json::from_str(text).map_err(|e| ServerError::Malformed(e.to_string()))
+}
+
+/// Handles a message of a client that has no seat yet, true once it got one
+async fn lobby_event(
+    wrapper: Wrapper,
+    session: &mut Session,
+    text: &str,
+) -> Result<bool, ServerError> {
+    match parse(text)? {
+        WsEvent::ConnectRq {
+            bot_token: Some(bot_token),
+            ..
+        } => {
+            let config = &wrapper.shared.config;
+            let name = config
+                .bot_tokens
+                .get(&bot_token)
+                .cloned()
+                .ok_or(ServerError::UnknownBot)?;
+            if !wrapper.register_bot(&name, session.sender.clone()) {
+                return Err(ServerError::BotConnected);
+            }
+
+            println!("Bot connected: {} as {}", session.connection_id, name);
+            session.connection_id = name.clone();
+            // the token is the identity, a bot that dropped mid-game is back in its seat
+            let game_id = wrapper.reconnect_client(&name, session.sender.clone());
+            let response = WsEvent::ConnectRs {
+                player_id: name.clone(),
+                resume_token: session::issue_token(&config.resume_secret, &name),
+                game_id: game_id.clone(),
+            };
+            reply(&session.sender, response).await;
+            if let Some(game_id) = game_id {
+                resume_seat(wrapper.clone(), game_id, name, &session.sender).await?;
+            }
+
+            // bots wait in the lobby until the tournament seats them
+            session.is_bot = true;
+            Ok(true)
+        }
+        WsEvent::ConnectRq { resume_token, .. } => {
+            let config = &wrapper.shared.config;
+            let secret = &config.resume_secret;
+            // a player who dropped mid-game takes their seat back
+            let known = resume_token
+                .and_then(|token| session::verify_token(secret, &token, config.resume_ttl));
+            let resumed = known.clone().and_then(|player_id| {
+                let game_id = wrapper.reconnect_client(&player_id, session.sender.clone())?;
+                Some((player_id, game_id))
+            });
+            let Some((player_id, game_id)) = resumed else {
+                // no seat to take back, the player still keeps their id and record
+                if let Some(player_id) = known.filter(|id| wrapper.claim_player(id)) {
+                    println!("Client known: {} as {}", session.connection_id, player_id);
+                    session.connection_id = player_id;
+                } else {
+                    wrapper.claim_player(&session.connection_id);
+                }
+                let response = WsEvent::ConnectRs {
+                    player_id: session.connection_id.clone(),
+                    resume_token: session::issue_token(secret, &session.connection_id),
+                    game_id: None,
+                };
+                reply(&session.sender, response).await;
+                return Ok(false);
+            };
+
+            println!(
+                "Client reconnected: {} as {}",
+                session.connection_id, player_id
+            );
+            session.connection_id = player_id.clone();
+            let response = WsEvent::ConnectRs {
+                player_id: player_id.clone(),
+                resume_token: session::issue_token(secret, &player_id),
+                game_id: Some(game_id.clone()),
+            };
+            reply(&session.sender, response).await;
+            resume_seat(wrapper.clone(), game_id, player_id, &session.sender).await?;
+            Ok(true)
+        }
+        WsEvent::CreateGameRq(mut rq) => {
+            rq.username = session.connection_id.clone();
+
+            // rejected placement, the client fixes it and retries
+            let response = game_engine::game_new(wrapper.clone(), rq)?;
+            if let WsEvent::CreateGameRs { game_id, .. } = &response {
+                // set up channels
+                wrapper.attach_client(
+                    game_id,
+                    Client::new(session.connection_id.clone(), session.sender.clone()),
+                );
+            }
+
+            // let room_receiver = wrapper.get_room_sender(&game_id).subscribe();
+            // broadband_handle = Some(broadband_consumer(room_receiver, self_chan_sender.clone()));
+
+            reply(&session.sender, response).await;
+            Ok(true)
+        }
+        WsEvent::JoinRq(mut rq) => {
+            let username = session.connection_id.clone();
+            let game_id = rq.game_id.clone();
+            rq.username = username.clone();
+
+            let response = game_engine::game_join(wrapper.clone(), rq)?;
+
+            // set up channels
+            wrapper.attach_client(
+                &game_id,
+                Client::new(username.clone(), session.sender.clone()),
+            );
+
+            // let room_receiver = wrapper.get_room_sender(&game_id).subscribe();
+            // broadband_handle = Some(broadband_consumer(room_receiver, self_chan_sender.clone()));
+
+            reply(&session.sender, response).await;
+
+            //this not delivered to p1... blocked fpr some reason and order break
+            // let _ = wrapper.get_room_sender(&game_id).send(json!(WsEvent::GameStart).to_string()).unwrap();
+
+            // send initial state for me & opponent
+            game_engine::notify_players(wrapper.clone(), &game_id).await;
+
+            Ok(true)
+        }
+        WsEvent::SpectateRq(rq) => {
+            let room = wrapper
+                .get_room_sender(&rq.game_id)
+                .ok_or_else(|| ServerError::NoSuchGame(rq.game_id.clone()))?;
+
+            // subscribe before the snapshot so no update falls in between
+            let room_receiver = room.subscribe();
+            let game_id = rq.game_id.clone();
+            let response = game_engine::game_spectate(wrapper.clone(), rq)?;
+            session.spectating = Some(game_id);
+            reply(&session.sender, response).await;
+
+            // one game at a time, spectators stay in this loop
+            if let Some(handle) = session.broadband_handle.take() {
+                handle.abort();
+            }
+            session.broadband_handle =
+                Some(broadband_consumer(room_receiver, session.sender.clone()));
+            Ok(false)
+        }
+        WsEvent::ChatRq(mut rq) => {
+            let game_id = session.spectating.clone().ok_or(ServerError::NotInGame)?;
+            if !session.chat_limiter.allow(Instant::now()) {
+                return Err(ServerError::RateLimited);
+            }
+
+            rq.game_id = game_id.clone();
+            rq.username = session.connection_id.clone();
+            let response = game_engine::game_chat(wrapper.clone(), rq, true)?;
+            game_engine::send_chat(wrapper.clone(), &game_id, response).await;
+            Ok(false)
+        }
+        WsEvent::PlayBotRq(mut rq) => {
+            rq.username = session.connection_id.clone();
+
+            let response = game_engine::game_vs_bot(wrapper.clone(), rq)?;
+            let WsEvent::CreateGameRs { game_id, .. } = &response else {
+                return Ok(false);
+            };
+            let game_id = game_id.clone();
+
+            wrapper.attach_client(
+                &game_id,
+                Client::new(session.connection_id.clone(), session.sender.clone()),
+            );
+            reply(&session.sender, response).await;
+
+            game_engine::notify_players(wrapper.clone(), &game_id).await;
+            Ok(true)
+        }
+        WsEvent::QueueRq(mut rq) => {
+            rq.username = session.connection_id.clone();
+
+            let response = game_engine::enqueue(wrapper.clone(), rq, session.sender.clone())?;
+            reply(&session.sender, response).await;
+            Ok(true)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Handles a message of a seated player
+async fn game_event(
+    wrapper: Wrapper,
+    session: &mut Session,
+    text: &str,
+) -> Result<(), ServerError> {
+    let event = parse(text)?;
+    let username = session.connection_id.clone();
+    if let WsEvent::FleetRs { ships } = event {
+        // asked for between tournament games, there is no game yet
+        return tournament::submit_fleet(&wrapper, &username, ships);
+    }
+    {
+        let state = wrapper.shared.state.read().unwrap();
+        if !state.client_games.contains_key(&username) {
+            return Err(ServerError::NotInGame);
         }
     }
+
+    match event {
+        WsEvent::TurnRq(mut rq) => {
+            let game_id = rq.game_id.clone();
+            rq.username = username;
+            game_engine::game_turn(wrapper.clone(), rq)?;
+
+            game_engine::notify_players(wrapper.clone(), &game_id).await;
+        }
+        WsEvent::SalvoRq(mut rq) => {
+            let game_id = rq.game_id.clone();
+            rq.username = username;
+            let response = game_engine::game_salvo(wrapper.clone(), rq)?;
+
+            // both sides see every shot of the salvo
+            let (me, opponent) = wrapper.get_clients(&game_id)?;
+            if let WsEvent::SalvoRs { results } = response {
+                opponent
+                    .send(WsEvent::SalvoRs {
+                        results: results.clone(),
+                    })
+                    .await;
+                me.send(WsEvent::SalvoRs { results }).await;
+            }
+
+            game_engine::notify_players(wrapper.clone(), &game_id).await;
+        }
+        WsEvent::ResignRq(mut rq) => {
+            let game_id = rq.game_id.clone();
+            rq.username = username;
+            game_engine::game_resign(wrapper.clone(), rq)?;
+
+            game_engine::notify_players(wrapper.clone(), &game_id).await;
+        }
+        WsEvent::RematchRq(mut rq) => {
+            rq.username = username;
+            let WsEvent::RematchRs {
+                game_id,
+                player_id,
+                round,
+                started,
+            } = game_engine::game_rematch(wrapper.clone(), rq)?
+            else {
+                return Ok(());
+            };
+
+            // opponent sees the offer, both see the restart
+            let (me, opponent) = wrapper.get_clients(&game_id)?;
+            for client in [&me, &opponent] {
+                let rs = WsEvent::RematchRs {
+                    game_id: game_id.clone(),
+                    player_id: player_id.clone(),
+                    round,
+                    started,
+                };
+                client.send(rs).await;
+            }
+
+            if started {
+                game_engine::notify_players(wrapper.clone(), &game_id).await;
+            }
+        }
+        WsEvent::ChatRq(mut rq) => {
+            if !session.chat_limiter.allow(Instant::now()) {
+                return Err(ServerError::RateLimited);
+            }
+
+            let game_id = rq.game_id.clone();
+            rq.username = username;
+            let response = game_engine::game_chat(wrapper.clone(), rq, false)?;
+            game_engine::send_chat(wrapper.clone(), &game_id, response).await;
+        }
+        WsEvent::StateRq(mut rq) => {
+            rq.username = username;
+            let response = game_engine::game_state(wrapper.clone(), rq);
+            reply(&session.sender, response).await;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Tells the opponent the player is back and replays where the game stands to the new socket
+async fn resume_seat(
+    wrapper: Wrapper,
+    game_id: String,
+    player_id: String,
+    self_chan_sender: &Sender<WsEvent>,
+) -> Result<(), ServerError> {
+    let (me, opponent) = wrapper.get_clients(&game_id)?;
+    let opponent = if me.id == player_id { opponent } else { me };
+    opponent
+        .send(WsEvent::OpponentReconnected {
+            player_id: player_id.clone(),
+        })
+        .await;
+
+    let response = game_engine::game_state(wrapper, StateRequest::new(game_id, player_id));
+    reply(self_chan_sender, response).await;
+    Ok(())
 }
 
 fn start_matchmaker(wrapper: Wrapper) {
@@ -307,15 +639,24 @@ fn start_matchmaker(wrapper: Wrapper) {
     });
 }
 
+/// Forwards a game room to a spectator socket, closes the socket once the game is gone
 fn broadband_consumer(
-    mut room_receiver: Receiver<String>,
-    self_channel_sender: Sender<String>,
+    mut room_receiver: Receiver<WsEvent>,
+    self_channel_sender: Sender<WsEvent>,
 ) -> JoinHandle<()> {
-    return tokio::spawn(async move {
-        while let Ok(msg) = room_receiver.recv().await {
-            if self_channel_sender.send(msg).await.is_err() {
-                break;
+    tokio::spawn(async move {
+        loop {
+            match room_receiver.recv().await {
+                Ok(msg) => {
+                    if self_channel_sender.send(msg).await.is_err() {
+                        return;
+                    }
+                }
+                // a slow spectator skips updates, the next state catches up
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
             }
         }
-    });
+        let _ = self_channel_sender.send(WsEvent::Disconnect).await;
+    })
 }
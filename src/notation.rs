@@ -0,0 +1,668 @@
+//! Games as shareable text, one shot per line:
+//!
+//! ```text
+//! [Game "k3Xz9Q"]
+//! [P1 "alice"]
+//! [P2 "bob"]
+//! [Seed "1234"]
+//! [Date "2026-10-18 12:00:00 UTC"]
+//! [Result "P1 allShipsSunk"]
+//! [Rules "{"width":10,...}"]
+//!
+//! P1 fleet A1-A4 C1-C3 ...
+//! P2 fleet J7-J10 ...
+//! P1 first
+//! P1 E5 hit
+//! P2 A1 miss
+//! ...
+//! P1 wins allShipsSunk
+//! ```
+//!
+//! Rows are letters and columns numbers from 1, as on the board. A fleet line after
+//! a `wins` line starts the next round. Only the first mover of a round is written,
+//! the rules decide every later turn, so an imported game is checked by playing it again.
+
+use crate::dto::{ClientId, GameId, GameOverReason, GameStatus, ShipsRaw, ShotResult};
+use crate::engine::{Action, GameCore, GameEvent, Point2d};
+use crate::game_engine::grid_as_json_single;
+use crate::rules::{GameMode, RuleSet};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SIDES: [&str; 2] = ["P1", "P2"];
+
+/// A game read back from notation, every move went through the rules again
+#[derive(Debug)]
+pub struct Replay {
+    pub game_id: GameId,
+    pub date: String,
+    pub game: GameCore,
+}
+
+pub fn export(game_id: &str, created: SystemTime, game: &GameCore) -> String {
+    let players: Vec<ClientId> = [game.p1.as_ref(), game.p2.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|p| p.name.clone())
+        .collect();
+    let side = |name: &str| {
+        let idx = players.iter().position(|p| p == name).unwrap_or(0);
+        SIDES[idx]
+    };
+
+    let result = match &game.result {
+        Some(result) => format!("{} {}", side(&result.winner), name_of(result.reason)),
+        None => "*".to_string(),
+    };
+    let mut text = String::new();
+    let _ = writeln!(text, "[Game \"{}\"]", game_id);
+    for (idx, name) in players.iter().enumerate() {
+        let _ = writeln!(text, "[{} \"{}\"]", SIDES[idx], name);
+    }
+    let _ = writeln!(text, "[Seed \"{}\"]", game.seed);
+    let _ = writeln!(text, "[Date \"{}\"]", format_date(created));
+    let _ = writeln!(text, "[Result \"{}\"]", result);
+    let rules = serde_json::to_string(&game.rules).unwrap_or_default();
+    let _ = writeln!(text, "[Rules \"{}\"]", rules);
+    let _ = writeln!(text);
+
+    for event in game.log.iter() {
+        match event {
+            GameEvent::Placed { player, ships } => {
+                let ships: Vec<String> = ships.iter().map(|s| ship_name(s)).collect();
+                let _ = writeln!(text, "{} fleet {}", side(player), ships.join(" "));
+            }
+            GameEvent::Shot { player, outcome } => {
+                let cell = cell_name(Point2d::new(outcome.x, outcome.y));
+                let result = name_of(outcome.result);
+                let _ = writeln!(text, "{} {} {}", side(player), cell, result);
+            }
+            GameEvent::Started { first, .. } => {
+                let _ = writeln!(text, "{} first", side(first));
+            }
+            GameEvent::GameOver(result) => {
+                let reason = name_of(result.reason);
+                let _ = writeln!(text, "{} wins {}", side(&result.winner), reason);
+            }
+            GameEvent::Joined { .. }
+            | GameEvent::TurnPassed { .. }
+            | GameEvent::RematchOffered { .. } => {}
+        }
+    }
+    text
+}
+
+/// Reads a game and plays it through the rules, any move they reject fails the import
+pub fn import(text: &str) -> Result<Replay, String> {
+    let mut headers = HashMap::new();
+    let mut lines = text.lines().enumerate().peekable();
+    while let Some((_, line)) = lines.next_if(|(_, l)| l.trim().is_empty() || l.starts_with('[')) {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (key, value) = line
+            .strip_prefix('[')
+            .and_then(|l| l.strip_suffix("\"]"))
+            .and_then(|l| l.split_once(" \""))
+            .ok_or(format!("Bad header {}", line))?;
+        headers.insert(key.to_string(), value.to_string());
+    }
+
+    let header = |key: &str| headers.get(key).ok_or(format!("Missing header {}", key));
+    let players = [header("P1")?.clone(), header("P2")?.clone()];
+    let seed = header("Seed")?
+        .parse()
+        .map_err(|_| "Seed must be a number".to_string())?;
+    let rules: RuleSet =
+        serde_json::from_str(header("Rules")?).map_err(|e| format!("Bad rules: {}", e))?;
+    rules.validate()?;
+
+    let mut game = GameCore::new(rules, seed);
+    let mut fleets: Vec<(ClientId, ShipsRaw)> = vec![];
+    let mut salvo: Vec<(usize, Point2d, ShotResult)> = vec![];
+    for (idx, line) in lines {
+        let line_no = idx + 1;
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some((&side, rest)) = words.split_first() else {
+            continue;
+        };
+        let player = SIDES
+            .iter()
+            .position(|s| *s == side)
+            .map(|i| players[i].clone())
+            .ok_or(format!("Line {}: unknown side {}", line_no, side))?;
+        let at_line = |e: String| format!("Line {}: {}", line_no, e);
+
+        // a salvo is every shot of a player in a row
+        let salvo_player = salvo.first().map(|_| game.current_turn.clone());
+        let is_shot = !matches!(rest.first(), Some(&"fleet" | &"first" | &"wins"));
+        if salvo_player.is_some_and(|p| !is_shot || p != player) {
+            fire_salvo(&mut game, &mut salvo)?;
+        }
+        // files without a first line leave the first turn to the seed
+        if fleets.len() == 2 && rest.first() != Some(&"first") {
+            place(&mut game, &mut fleets, None).map_err(at_line)?;
+        }
+
+        match rest {
+            ["fleet", ships @ ..] => {
+                let ships = ships
+                    .iter()
+                    .map(|s| parse_ship(s))
+                    .collect::<Result<ShipsRaw, String>>()
+                    .map_err(at_line)?;
+                fleets.push((player, ships));
+            }
+            ["first"] => {
+                if fleets.len() != 2 {
+                    return Err(at_line("No fleets to start a round with".to_string()));
+                }
+                place(&mut game, &mut fleets, Some(&player)).map_err(at_line)?;
+                if game.current_turn != player {
+                    return Err(at_line(format!("{} does not move first", side)));
+                }
+            }
+            ["wins", reason] => {
+                let reason: GameOverReason = value_of(reason).map_err(at_line)?;
+                if reason != GameOverReason::AllShipsSunk {
+                    let loser = players.iter().find(|p| **p != player);
+                    let action = Action::Forfeit {
+                        player: loser.cloned().unwrap_or_default(),
+                        reason,
+                    };
+                    game.apply(action).map_err(|e| at_line(e.to_string()))?;
+                }
+                let decided = game
+                    .result
+                    .as_ref()
+                    .is_some_and(|r| r.winner == player && r.reason == reason);
+                if game.status != GameStatus::GameOver || !decided {
+                    return Err(at_line(format!("{} has not won yet", side)));
+                }
+            }
+            [cell, result] => {
+                let at = parse_cell(cell).map_err(at_line)?;
+                let result: ShotResult = value_of(result).map_err(at_line)?;
+                if game.rules.mode == GameMode::Salvo {
+                    if salvo.is_empty() && game.current_turn != player {
+                        return Err(at_line("Not your turn".to_string()));
+                    }
+                    salvo.push((line_no, at, result));
+                    continue;
+                }
+                let events = game
+                    .apply(Action::Shoot { player, at })
+                    .map_err(|e| at_line(e.to_string()))?;
+                check_shots(&events, &[(line_no, at, result)])?;
+            }
+            _ => return Err(at_line(format!("Unreadable move {}", line.trim()))),
+        }
+    }
+    fire_salvo(&mut game, &mut salvo)?;
+    if fleets.len() == 2 {
+        place(&mut game, &mut fleets, None)?;
+    }
+
+    let result = match &game.result {
+        Some(result) => {
+            let idx = players.iter().position(|p| *p == result.winner);
+            format!(
+                "{} {}",
+                idx.map_or("", |i| SIDES[i]),
+                name_of(result.reason)
+            )
+        }
+        None => "*".to_string(),
+    };
+    if headers.get("Result").is_some_and(|r| *r != result) {
+        return Err(format!(
+            "Result header does not match the moves, {}",
+            result
+        ));
+    }
+
+    Ok(Replay {
+        game_id: headers.get("Game").cloned().unwrap_or_default(),
+        date: headers.get("Date").cloned().unwrap_or_default(),
+        game,
+    })
+}
+
+/// Seats both fleets of a round. The first mover of the first round is taken from
+/// the file, later rounds follow the rematch rule.
+fn place(
+    game: &mut GameCore,
+    fleets: &mut Vec<(ClientId, ShipsRaw)>,
+    first: Option<&ClientId>,
+) -> Result<(), String> {
+    if game.status == GameStatus::WaitingPlayers {
+        game.first = first.cloned();
+    }
+    for (player, ships) in fleets.drain(..) {
+        let action = match game.status {
+            GameStatus::WaitingPlayers => Action::Join { player, ships },
+            _ => Action::Rematch { player, ships },
+        };
+        game.apply(action).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Applies the collected salvo of the player to move
+fn fire_salvo(
+    game: &mut GameCore,
+    salvo: &mut Vec<(usize, Point2d, ShotResult)>,
+) -> Result<(), String> {
+    let Some(&(line_no, ..)) = salvo.first() else {
+        return Ok(());
+    };
+    let action = Action::Salvo {
+        player: game.current_turn.clone(),
+        shots: salvo.iter().map(|(_, at, _)| *at).collect(),
+    };
+    let events = game
+        .apply(action)
+        .map_err(|e| format!("Line {}: {}", line_no, e))?;
+    check_shots(&events, salvo)?;
+    salvo.clear();
+    Ok(())
+}
+
+/// The written results have to be the ones the rules gave
+fn check_shots(
+    events: &[GameEvent],
+    written: &[(usize, Point2d, ShotResult)],
+) -> Result<(), String> {
+    let outcomes = events.iter().filter_map(|e| match e {
+        GameEvent::Shot { outcome, .. } => Some(outcome),
+        _ => None,
+    });
+    for (outcome, (line_no, at, result)) in outcomes.zip(written) {
+        if outcome.result != *result {
+            return Err(format!(
+                "Line {}: {} is {}, not {}",
+                line_no,
+                cell_name(*at),
+                name_of(outcome.result),
+                name_of(*result)
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Lowercase name of an enum, as in the JSON API
+fn name_of(value: impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => String::new(),
+    }
+}
+
+fn value_of<T: DeserializeOwned>(name: &str) -> Result<T, String> {
+    serde_json::from_value(serde_json::Value::String(name.to_string()))
+        .map_err(|_| format!("Unknown word {}", name))
+}
+
+/// `E5` is row 4, column 4
+fn cell_name(p: Point2d) -> String {
+    format!("{}{}", (b'A' + p.x as u8) as char, p.y + 1)
+}
+
+fn parse_cell(cell: &str) -> Result<Point2d, String> {
+    let mut chars = cell.chars();
+    let row = chars
+        .next()
+        .filter(char::is_ascii_uppercase)
+        .ok_or(format!("Bad cell {}", cell))?;
+    let column: usize = chars
+        .as_str()
+        .parse()
+        .ok()
+        .filter(|c| *c > 0)
+        .ok_or(format!("Bad cell {}", cell))?;
+    Ok(Point2d::new((row as u8 - b'A') as usize, column - 1))
+}
+
+/// First and last cell, `A1-A4`, or the cell of a single-deck ship
+fn ship_name(ship: &[(usize, usize)]) -> String {
+    let (Some(first), Some(last)) = (ship.iter().min(), ship.iter().max()) else {
+        return String::new();
+    };
+    let first = cell_name(Point2d::new(first.0, first.1));
+    if ship.len() == 1 {
+        return first;
+    }
+    format!("{}-{}", first, cell_name(Point2d::new(last.0, last.1)))
+}
+
+fn parse_ship(ship: &str) -> Result<Vec<(usize, usize)>, String> {
+    let (first, last) = ship.split_once('-').unwrap_or((ship, ship));
+    let (first, last) = (parse_cell(first)?, parse_cell(last)?);
+    if first.x != last.x && first.y != last.y {
+        return Err(format!("Ship {} is not straight", ship));
+    }
+    let mut cells = vec![];
+    for x in first.x.min(last.x)..=first.x.max(last.x) {
+        for y in first.y.min(last.y)..=first.y.max(last.y) {
+            cells.push((x, y));
+        }
+    }
+    Ok(cells)
+}
+
+/// Checks a notation file, `battleship replay game.txt`
+pub fn run(args: &[String]) {
+    let usage = "usage: battleship replay <file> [--shots N]";
+    let Some(path) = args.first() else {
+        println!("{}", usage);
+        return;
+    };
+    let position = match args.get(1..) {
+        Some([flag, n]) if flag == "--shots" => match n.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => {
+                println!("{}", usage);
+                return;
+            }
+        },
+        Some([]) | None => None,
+        Some(_) => {
+            println!("{}", usage);
+            return;
+        }
+    };
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("Cannot read {}: {}", path, e);
+            return;
+        }
+    };
+
+    match import(&text) {
+        Ok(Replay {
+            game_id,
+            date,
+            game,
+        }) => {
+            let shots = game
+                .log
+                .iter()
+                .filter(|e| matches!(e, GameEvent::Shot { .. }))
+                .count();
+            println!(
+                "Game {} of {}: {} shots, {} rounds",
+                game_id, date, shots, game.round
+            );
+            match &game.result {
+                Some(result) => println!("{} won, {}", result.winner, name_of(result.reason)),
+                None => println!("unfinished"),
+            }
+            if let Some(shots) = position {
+                print_boards(&game.after_shots(shots), shots);
+            }
+        }
+        Err(e) => println!("Invalid replay: {}", e),
+    }
+}
+
+/// Both fleets in the open, `#` ship, `x` hit, `_` miss, `~` revealed
+fn print_boards(game: &GameCore, shots: usize) {
+    println!("After {} shots, round {}", shots, game.round);
+    for (side, player) in SIDES.iter().zip([game.p1.as_ref(), game.p2.as_ref()]) {
+        let Some(player) = player else {
+            continue;
+        };
+        let to_move = if game.current_turn == player.name && game.status == GameStatus::Progress {
+            ", to move"
+        } else {
+            ""
+        };
+        println!("{} {}{}", side, player.name, to_move);
+        let columns: Vec<String> = (1..=game.rules.width)
+            .map(|y| format!("{:>3}", y))
+            .collect();
+        println!("  {}", columns.concat());
+        for (x, row) in grid_as_json_single(player, false).iter().enumerate() {
+            let cells: Vec<String> = row.iter().map(|c| format!("{:>3}", c)).collect();
+            println!("{} {}", (b'A' + x as u8) as char, cells.concat());
+        }
+    }
+}
+
+/// UTC, `2026-10-18 12:00:00 UTC`
+fn format_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+
+    // days since 1970-01-01 to a civil date, Howard Hinnant's algorithm
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rest / 3_600,
+        rest % 3_600 / 60,
+        rest % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot;
+    use crate::bot::bot_id;
+    use crate::rules::random_fleet;
+    use crate::rules::tests::classic_fleet;
+    use crate::rules::TurnPolicy;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    /// Random untouched cells until the game, or the whole series, is decided
+    fn play_out(game: &mut GameCore, rng: &mut StdRng) {
+        for _ in 0..2000 {
+            if game.status == GameStatus::GameOver {
+                if game.apply(Action::NextRound).unwrap().is_empty() {
+                    return;
+                }
+                continue;
+            }
+            let player = game.current_turn.clone();
+            let mut targets = game.opponent_of(&player).unwrap().untouched_cells();
+            targets.shuffle(rng);
+            let action = match game.rules.mode {
+                GameMode::Classic => Action::Shoot {
+                    player,
+                    at: targets[0],
+                },
+                GameMode::Salvo => {
+                    targets.truncate(game.player(&player).unwrap().alive_ships());
+                    Action::Salvo {
+                        player,
+                        shots: targets,
+                    }
+                }
+            };
+            game.apply(action).unwrap();
+        }
+        panic!("game did not finish");
+    }
+
+    fn joined(rules: RuleSet, seed: u64) -> GameCore {
+        let mut game = GameCore::new(rules, seed);
+        let mut fleets = StdRng::seed_from_u64(seed);
+        for player in ["alice", "bob"] {
+            let ships = random_fleet(&game.rules, &mut fleets).unwrap();
+            let join = Action::Join {
+                player: player.to_string(),
+                ships,
+            };
+            game.apply(join).unwrap();
+        }
+        game
+    }
+
+    /// The log without rematch offers, the notation does not keep them
+    fn moves(game: &GameCore) -> Vec<GameEvent> {
+        game.log
+            .iter()
+            .filter(|e| !matches!(e, GameEvent::RematchOffered { .. }))
+            .cloned()
+            .collect()
+    }
+
+    fn round_trip(game: &GameCore) -> GameCore {
+        let text = export("g1", UNIX_EPOCH, game);
+        match import(&text) {
+            Ok(replay) => replay.game,
+            Err(e) => panic!("{}\n{}", e, text),
+        }
+    }
+
+    #[test]
+    fn round_trip_keeps_every_event() {
+        let variants = [
+            RuleSet::classic(),
+            RuleSet {
+                turn_policy: TurnPolicy::Alternate,
+                auto_reveal: false,
+                ..RuleSet::classic()
+            },
+            RuleSet {
+                mode: GameMode::Salvo,
+                ..RuleSet::classic()
+            },
+            RuleSet {
+                best_of: 3,
+                ..RuleSet::classic()
+            },
+        ];
+        for (seed, rules) in variants.into_iter().enumerate() {
+            let mut game = joined(rules, seed as u64);
+            play_out(&mut game, &mut StdRng::seed_from_u64(seed as u64));
+
+            let imported = round_trip(&game);
+            assert_eq!(moves(&imported), moves(&game));
+            assert_eq!(imported.result, game.result);
+            assert_eq!(imported.round, game.round);
+        }
+    }
+
+    #[test]
+    fn bot_games_round_trip() {
+        // dealt as `game_vs_bot` does, the bot's id from the game rng, its fleet from the seed
+        for seed in 0..40 {
+            let mut game = GameCore::new(RuleSet::classic(), seed);
+            let join = Action::Join {
+                player: "human".to_string(),
+                ships: classic_fleet(),
+            };
+            game.apply(join).unwrap();
+            let player = bot_id(&mut game.rng);
+            let ships = bot::fleet(&game.rules, seed, 1).unwrap();
+            game.apply(Action::Join { player, ships }).unwrap();
+            play_out(&mut game, &mut StdRng::seed_from_u64(seed));
+
+            assert_eq!(moves(&round_trip(&game)), moves(&game), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn rematch_rounds_round_trip() {
+        let mut game = joined(RuleSet::classic(), 5);
+        let mut rng = StdRng::seed_from_u64(5);
+        play_out(&mut game, &mut rng);
+        for player in ["bob", "alice"] {
+            let rematch = Action::Rematch {
+                player: player.to_string(),
+                ships: random_fleet(&game.rules, &mut rng).unwrap(),
+            };
+            game.apply(rematch).unwrap();
+        }
+        play_out(&mut game, &mut rng);
+
+        let imported = round_trip(&game);
+        assert_eq!(imported.round, 2);
+        assert_eq!(moves(&imported), moves(&game));
+    }
+
+    #[test]
+    fn unfinished_game_without_first_lines_still_imports() {
+        let mut game = joined(RuleSet::classic(), 3);
+        let first = game.current_turn.clone();
+        game.apply(Action::Shoot {
+            player: first,
+            at: Point2d::new(0, 0),
+        })
+        .unwrap();
+
+        let text = export("g1", UNIX_EPOCH, &game);
+        assert!(text.contains("[Result \"*\"]"));
+        let old: Vec<&str> = text.lines().filter(|l| !l.ends_with(" first")).collect();
+        let imported = import(&old.join("\n")).unwrap().game;
+        assert_eq!(moves(&imported), moves(&game));
+        assert_eq!(imported.status, GameStatus::Progress);
+    }
+
+    #[test]
+    fn rejects_moves_the_rules_did_not_give() {
+        let mut game = joined(RuleSet::classic(), 9);
+        play_out(&mut game, &mut StdRng::seed_from_u64(9));
+        let text = export("g1", UNIX_EPOCH, &game);
+
+        let lied = text.replacen(" miss", " hit", 1);
+        assert!(import(&lied).unwrap_err().contains(", not hit"));
+
+        let (p1, p2) = ("P1 first", "P2 first");
+        let swapped = if text.contains(p1) {
+            text.replace(p1, p2)
+        } else {
+            text.replace(p2, p1)
+        };
+        assert!(import(&swapped).is_err());
+
+        let wrong_result = text.replace("[Result \"P1", "[Result \"P2");
+        let wrong_result = if wrong_result == text {
+            text.replace("[Result \"P2", "[Result \"P1")
+        } else {
+            wrong_result
+        };
+        assert!(import(&wrong_result)
+            .unwrap_err()
+            .starts_with("Result header"));
+    }
+
+    #[test]
+    fn cells_and_ships_read_back() {
+        for cell in [Point2d::new(0, 0), Point2d::new(4, 9), Point2d::new(25, 25)] {
+            assert_eq!(parse_cell(&cell_name(cell)), Ok(cell));
+        }
+        assert_eq!(cell_name(Point2d::new(4, 4)), "E5");
+        assert!(parse_cell("e5").is_err());
+        assert!(parse_cell("E0").is_err());
+
+        let ship = vec![(2, 4), (2, 5), (2, 6)];
+        assert_eq!(ship_name(&ship), "C5-C7");
+        assert_eq!(parse_ship("C5-C7"), Ok(ship));
+        assert!(parse_ship("A1-B2").is_err());
+    }
+}
@@ -0,0 +1,542 @@
+use crate::dto::ShipsRaw;
+use crate::engine::{Point2d, RuleError};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// Upper bound for board sides, keeps grids addressable as A..Z
+pub const MAX_BOARD_SIZE: usize = 26;
+pub const MAX_TURN_SECONDS: u64 = 3600;
+pub const MAX_CLOCK_SECONDS: u64 = 3 * 3600;
+pub const MAX_BEST_OF: u32 = 9;
+/// Fresh starts `random_fleet` makes before it gives up on a fleet
+const MAX_FLEET_ATTEMPTS: usize = 200;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FleetEntry {
+    pub length: usize,
+    pub count: usize,
+}
+
+impl FleetEntry {
+    pub fn new(length: usize, count: usize) -> Self {
+        Self { length, count }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum GameMode {
+    /// One shot per turn
+    #[default]
+    Classic,
+    /// As many shots per turn as the shooter has ships afloat
+    Salvo,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TurnPolicy {
+    /// Shooter keeps the turn while hitting
+    #[default]
+    ExtraTurnOnHit,
+    /// Turn passes after every shot
+    Alternate,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum TimeoutPolicy {
+    /// Server shoots a random cell (or a random salvo) for the idle player
+    RandomShot,
+    /// Idle player loses the game
+    Forfeit,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TurnTimer {
+    pub seconds: u64,
+    pub on_timeout: TimeoutPolicy,
+}
+
+/// Board size, fleet composition and rule options of a game.
+/// `x` addresses rows (0..height), `y` addresses columns (0..width).
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RuleSet {
+    pub width: usize,
+    pub height: usize,
+    pub fleet: Vec<FleetEntry>,
+    pub mode: GameMode,
+    /// Ignored in salvo mode, a salvo always passes the turn
+    pub turn_policy: TurnPolicy,
+    /// Reveal the cells around a sunk ship, they can't hold another ship anyway
+    pub auto_reveal: bool,
+    /// Time limit for a single move, no limit when absent
+    pub turn_timer: Option<TurnTimer>,
+    /// Chess clock: total thinking time per player, runs only during own turns
+    pub clock_seconds: Option<u64>,
+    /// Rounds in a series, 1 for a single game
+    pub best_of: u32,
+    /// Spectators may write to the game chat, they can always read it
+    pub spectator_chat: bool,
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        Self::classic()
+    }
+}
+
+impl RuleSet {
+    /// 10x10, 1x4 2x3 3x2 4x1
+    pub fn classic() -> Self {
+        Self {
+            width: 10,
+            height: 10,
+            fleet: vec![
+                FleetEntry::new(4, 1),
+                FleetEntry::new(3, 2),
+                FleetEntry::new(2, 3),
+                FleetEntry::new(1, 4),
+            ],
+            mode: GameMode::Classic,
+            turn_policy: TurnPolicy::ExtraTurnOnHit,
+            auto_reveal: true,
+            turn_timer: None,
+            clock_seconds: None,
+            best_of: 1,
+            spectator_chat: false,
+        }
+    }
+
+    /// Sanity check for client supplied rules
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 {
+            return Err("Board must not be empty".to_string());
+        }
+        if self.width > MAX_BOARD_SIZE || self.height > MAX_BOARD_SIZE {
+            return Err(format!("Board is limited to {0}x{0}", MAX_BOARD_SIZE));
+        }
+        if self.ship_count() == 0 {
+            return Err("Fleet must not be empty".to_string());
+        }
+
+        let mut seen = HashSet::new();
+        for entry in self.fleet.iter() {
+            if entry.length == 0 || entry.length > self.width.max(self.height) {
+                return Err(format!("Ship of length {} does not fit", entry.length));
+            }
+            if !seen.insert(entry.length) {
+                return Err(format!("Ship length {} listed twice", entry.length));
+            }
+        }
+
+        if let Some(timer) = self.turn_timer {
+            if timer.seconds == 0 || timer.seconds > MAX_TURN_SECONDS {
+                return Err(format!(
+                    "Turn timer must be 1 to {} seconds",
+                    MAX_TURN_SECONDS
+                ));
+            }
+        }
+
+        if let Some(seconds) = self.clock_seconds {
+            if seconds == 0 || seconds > MAX_CLOCK_SECONDS {
+                return Err(format!("Clock must be 1 to {} seconds", MAX_CLOCK_SECONDS));
+            }
+        }
+
+        if self.best_of.is_multiple_of(2) || self.best_of > MAX_BEST_OF {
+            return Err(format!(
+                "Series must be an odd best of 1 to {}",
+                MAX_BEST_OF
+            ));
+        }
+
+        // ships with their mandatory gap can't cover more than the board
+        let area: usize = self
+            .fleet
+            .iter()
+            .map(|e| (e.length + 1) * 2 * e.count)
+            .sum();
+        if area > (self.width + 1) * (self.height + 1) {
+            return Err("Fleet does not fit on the board".to_string());
+        }
+        // bots and series deal fleets at random, a fleet that only fits by hand won't do
+        if random_fleet(self, &mut StdRng::seed_from_u64(0)).is_err() {
+            return Err("Fleet is too dense for the board".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn is_timed(&self) -> bool {
+        self.turn_timer.is_some() || self.clock_seconds.is_some()
+    }
+
+    /// Round wins needed to take the series
+    pub fn wins_needed(&self) -> u32 {
+        self.best_of / 2 + 1
+    }
+
+    pub fn ship_count(&self) -> usize {
+        self.fleet.iter().map(|e| e.count).sum()
+    }
+
+    pub fn contains(&self, p: Point2d) -> bool {
+        p.x < self.height && p.y < self.width
+    }
+
+    /// All 8 cells around the point that fit on the board
+    pub fn neighbours(&self, p: Point2d) -> Vec<Point2d> {
+        let mut result = Vec::with_capacity(8);
+        for dx in -1i64..=1 {
+            for dy in -1i64..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+                let (x, y) = (p.x as i64 + dx, p.y as i64 + dy);
+                if x < 0 || y < 0 || !self.contains(Point2d::new(x as usize, y as usize)) {
+                    continue;
+                }
+                result.push(Point2d::new(x as usize, y as usize));
+            }
+        }
+        result
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlacementError {
+    EmptyShip,
+    OutOfBounds(Point2d),
+    NotStraight(Point2d),
+    NotContiguous(Point2d),
+    Overlap(Point2d),
+    Touching(Point2d),
+    WrongFleet {
+        length: usize,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for PlacementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlacementError::EmptyShip => write!(f, "Ship without cells"),
+            PlacementError::OutOfBounds(p) => write!(f, "Cell {}:{} is out of the board", p.x, p.y),
+            PlacementError::NotStraight(p) => {
+                write!(f, "Ship at {}:{} is not a straight line", p.x, p.y)
+            }
+            PlacementError::NotContiguous(p) => write!(f, "Ship at {}:{} has gaps", p.x, p.y),
+            PlacementError::Overlap(p) => write!(f, "Ships overlap at {}:{}", p.x, p.y),
+            PlacementError::Touching(p) => write!(f, "Ships touch at {}:{}", p.x, p.y),
+            PlacementError::WrongFleet {
+                length,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "Expected {} ship(s) of length {}, got {}",
+                expected, length, actual
+            ),
+        }
+    }
+}
+
+/// Checks bounds, ship shape, overlaps, touching (incl. diagonal) and fleet composition
+pub fn validate_fleet(ships: &ShipsRaw, rules: &RuleSet) -> Result<(), PlacementError> {
+    let mut owner: HashMap<Point2d, usize> = HashMap::new();
+    for (idx, ship) in ships.iter().enumerate() {
+        validate_ship(ship, rules)?;
+        for &(x, y) in ship {
+            let p = Point2d::new(x, y);
+            if owner.insert(p, idx).is_some() {
+                return Err(PlacementError::Overlap(p));
+            }
+        }
+    }
+
+    for (idx, ship) in ships.iter().enumerate() {
+        for &(x, y) in ship {
+            for n in rules.neighbours(Point2d::new(x, y)) {
+                if owner.get(&n).is_some_and(|other| *other != idx) {
+                    return Err(PlacementError::Touching(n));
+                }
+            }
+        }
+    }
+
+    let mut counts: HashMap<usize, usize> = HashMap::new();
+    for ship in ships {
+        *counts.entry(ship.len()).or_default() += 1;
+    }
+    let expected: HashMap<usize, usize> = rules.fleet.iter().map(|e| (e.length, e.count)).collect();
+    let lengths: HashSet<usize> = counts.keys().chain(expected.keys()).copied().collect();
+    let mut lengths: Vec<usize> = lengths.into_iter().collect();
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+    for length in lengths {
+        let actual = counts.get(&length).copied().unwrap_or(0);
+        let expected = expected.get(&length).copied().unwrap_or(0);
+        if actual != expected {
+            return Err(PlacementError::WrongFleet {
+                length,
+                expected,
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Random legal placement of the rule set's fleet, gives up after `MAX_FLEET_ATTEMPTS`
+pub fn random_fleet(rules: &RuleSet, rng: &mut impl Rng) -> Result<ShipsRaw, RuleError> {
+    let mut lengths: Vec<usize> = rules
+        .fleet
+        .iter()
+        .flat_map(|e| std::iter::repeat_n(e.length, e.count))
+        .collect();
+    lengths.sort_unstable_by(|a, b| b.cmp(a));
+
+    // a dense fleet may need a few fresh starts
+    for _ in 0..MAX_FLEET_ATTEMPTS {
+        if let Some(ships) = try_place(rules, &lengths, rng) {
+            return Ok(ships);
+        }
+    }
+    Err(RuleError::NoRandomFleet)
+}
+
+fn try_place(rules: &RuleSet, lengths: &[usize], rng: &mut impl Rng) -> Option<ShipsRaw> {
+    let mut blocked: HashSet<Point2d> = HashSet::new();
+    let mut ships = Vec::with_capacity(lengths.len());
+
+    for &length in lengths {
+        let mut placed = None;
+        for _ in 0..100 {
+            let horizontal = rng.random_bool(0.5);
+            let (rows, cols) = if horizontal {
+                (rules.height, rules.width + 1 - length.min(rules.width))
+            } else {
+                (rules.height + 1 - length.min(rules.height), rules.width)
+            };
+            if rows == 0 || cols == 0 || length > rules.width.max(rules.height) {
+                continue;
+            }
+            let (x0, y0) = (rng.random_range(0..rows), rng.random_range(0..cols));
+            let cells: Vec<Point2d> = (0..length)
+                .map(|i| {
+                    if horizontal {
+                        Point2d::new(x0, y0 + i)
+                    } else {
+                        Point2d::new(x0 + i, y0)
+                    }
+                })
+                .collect();
+            if cells
+                .iter()
+                .all(|c| rules.contains(*c) && !blocked.contains(c))
+            {
+                placed = Some(cells);
+                break;
+            }
+        }
+
+        let cells = placed?;
+        for c in cells.iter() {
+            blocked.insert(*c);
+            blocked.extend(rules.neighbours(*c));
+        }
+        ships.push(cells.iter().map(|c| (c.x, c.y)).collect());
+    }
+
+    Some(ships)
+}
+
+fn validate_ship(ship: &[(usize, usize)], rules: &RuleSet) -> Result<(), PlacementError> {
+    let Some(&(x0, y0)) = ship.first() else {
+        return Err(PlacementError::EmptyShip);
+    };
+    let start = Point2d::new(x0, y0);
+
+    for &(x, y) in ship {
+        if !rules.contains(Point2d::new(x, y)) {
+            return Err(PlacementError::OutOfBounds(Point2d::new(x, y)));
+        }
+    }
+
+    let horizontal = ship.iter().all(|&(x, _)| x == x0);
+    let vertical = ship.iter().all(|&(_, y)| y == y0);
+    if !horizontal && !vertical {
+        return Err(PlacementError::NotStraight(start));
+    }
+
+    let mut line: Vec<usize> = ship
+        .iter()
+        .map(|&(x, y)| if horizontal { y } else { x })
+        .collect();
+    line.sort_unstable();
+    for pair in line.windows(2) {
+        if pair[1] == pair[0] {
+            return Err(PlacementError::Overlap(start));
+        }
+        if pair[1] != pair[0] + 1 {
+            return Err(PlacementError::NotContiguous(start));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    /// Legal classic fleet, ships along rows 0, 2, 4 and 6
+    pub(crate) fn classic_fleet() -> ShipsRaw {
+        vec![
+            vec![(0, 0), (0, 1), (0, 2), (0, 3)],
+            vec![(2, 0), (2, 1), (2, 2)],
+            vec![(2, 4), (2, 5), (2, 6)],
+            vec![(4, 0), (4, 1)],
+            vec![(4, 3), (4, 4)],
+            vec![(4, 6), (4, 7)],
+            vec![(6, 0)],
+            vec![(6, 2)],
+            vec![(6, 4)],
+            vec![(6, 6)],
+        ]
+    }
+
+    /// Classic fleet with the last single-deck ship moved to `cells`
+    fn with_last(cells: Vec<(usize, usize)>) -> ShipsRaw {
+        let mut ships = classic_fleet();
+        *ships.last_mut().unwrap() = cells;
+        ships
+    }
+
+    #[test]
+    fn accepts_classic_fleet() {
+        assert_eq!(
+            validate_fleet(&classic_fleet(), &RuleSet::classic()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn rejects_cells_off_the_board() {
+        let ships = with_last(vec![(10, 9)]);
+        assert_eq!(
+            validate_fleet(&ships, &RuleSet::classic()),
+            Err(PlacementError::OutOfBounds(Point2d::new(10, 9)))
+        );
+    }
+
+    #[test]
+    fn rejects_bent_and_gapped_ships() {
+        let mut bent = classic_fleet();
+        bent[0] = vec![(0, 0), (0, 1), (0, 2), (1, 2)];
+        assert_eq!(
+            validate_fleet(&bent, &RuleSet::classic()),
+            Err(PlacementError::NotStraight(Point2d::new(0, 0)))
+        );
+
+        let mut gapped = classic_fleet();
+        gapped[0] = vec![(0, 0), (0, 1), (0, 3), (0, 4)];
+        assert_eq!(
+            validate_fleet(&gapped, &RuleSet::classic()),
+            Err(PlacementError::NotContiguous(Point2d::new(0, 0)))
+        );
+    }
+
+    #[test]
+    fn rejects_overlapping_ships() {
+        let ships = with_last(vec![(6, 4)]);
+        assert_eq!(
+            validate_fleet(&ships, &RuleSet::classic()),
+            Err(PlacementError::Overlap(Point2d::new(6, 4)))
+        );
+    }
+
+    #[test]
+    fn rejects_ships_touching_diagonally() {
+        // (5, 8) is diagonal to the end of the ship at (4, 6)-(4, 7)
+        let ships = with_last(vec![(5, 8)]);
+        assert!(matches!(
+            validate_fleet(&ships, &RuleSet::classic()),
+            Err(PlacementError::Touching(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_wrong_fleet() {
+        let mut ships = classic_fleet();
+        ships.pop();
+        assert_eq!(
+            validate_fleet(&ships, &RuleSet::classic()),
+            Err(PlacementError::WrongFleet {
+                length: 1,
+                expected: 4,
+                actual: 3
+            })
+        );
+
+        let ships = with_last(vec![(8, 0), (8, 1)]);
+        assert_eq!(
+            validate_fleet(&ships, &RuleSet::classic()),
+            Err(PlacementError::WrongFleet {
+                length: 2,
+                expected: 3,
+                actual: 4
+            })
+        );
+    }
+
+    #[test]
+    fn random_fleets_are_legal() {
+        let small = RuleSet {
+            width: 6,
+            height: 7,
+            fleet: vec![FleetEntry::new(3, 1), FleetEntry::new(2, 2)],
+            ..RuleSet::classic()
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        for rules in [RuleSet::classic(), small] {
+            for _ in 0..50 {
+                let ships = random_fleet(&rules, &mut rng).unwrap();
+                assert_eq!(validate_fleet(&ships, &rules), Ok(()));
+            }
+        }
+    }
+
+    #[test]
+    fn fleet_that_fits_only_by_hand_is_rejected_in_time() {
+        // one single-deck ship on every other cell of every other row
+        let dense = RuleSet {
+            width: 14,
+            height: 14,
+            fleet: vec![FleetEntry::new(1, 49)],
+            ..RuleSet::classic()
+        };
+        let by_hand: ShipsRaw = (0..7)
+            .flat_map(|x| (0..7).map(move |y| vec![(2 * x, 2 * y)]))
+            .collect();
+        assert_eq!(validate_fleet(&by_hand, &dense), Ok(()));
+
+        let started = Instant::now();
+        assert_eq!(
+            random_fleet(&dense, &mut StdRng::seed_from_u64(1)),
+            Err(RuleError::NoRandomFleet)
+        );
+        assert!(dense.validate().is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
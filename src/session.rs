@@ -0,0 +1,100 @@
+use crate::dto::ClientId;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Resume token for a player, `<player_id>.<issued>.<hex hmac>` signed with the server
+/// secret. `issued` is in Unix seconds, every connect hands out a fresh token.
+pub fn issue_token(secret: &[u8], player_id: &str) -> String {
+    issue_at(secret, player_id, now_secs())
+}
+
+/// Player id the token was issued for, None if it is malformed, signed by another secret
+/// or older than `ttl`
+pub fn verify_token(secret: &[u8], token: &str, ttl: Duration) -> Option<ClientId> {
+    verify_at(secret, token, ttl, now_secs())
+}
+
+fn issue_at(secret: &[u8], player_id: &str, issued: u64) -> String {
+    let payload = format!("{}.{}", player_id, issued);
+    let signature = hex::encode(sign(secret, &payload).finalize().into_bytes());
+    format!("{}.{}", payload, signature)
+}
+
+fn verify_at(secret: &[u8], token: &str, ttl: Duration, now: u64) -> Option<ClientId> {
+    let (payload, signature) = token.rsplit_once('.')?;
+    let signature = hex::decode(signature).ok()?;
+    sign(secret, payload).verify_slice(&signature).ok()?;
+
+    let (player_id, issued) = payload.rsplit_once('.')?;
+    let issued: u64 = issued.parse().ok()?;
+    if issued > now || now - issued > ttl.as_secs() {
+        return None;
+    }
+    Some(player_id.to_string())
+}
+
+fn sign(secret: &[u8], payload: &str) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret).expect("hmac takes keys of any size");
+    mac.update(payload.as_bytes());
+    mac
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"secret";
+    const DAY: Duration = Duration::from_secs(24 * 3600);
+
+    #[test]
+    fn fresh_token_names_its_player() {
+        let token = issue_token(SECRET, "player.1");
+        assert_eq!(
+            verify_token(SECRET, &token, DAY),
+            Some("player.1".to_string())
+        );
+    }
+
+    #[test]
+    fn tampered_token_is_rejected() {
+        let token = issue_at(SECRET, "alice", 1000);
+        let forged = token.replacen("alice", "mallory", 1);
+        assert_eq!(verify_at(SECRET, &forged, DAY, 1000), None);
+
+        let backdated = token.replacen(".1000.", ".2000.", 1);
+        assert_eq!(verify_at(SECRET, &backdated, DAY, 2000), None);
+
+        let (payload, _) = token.rsplit_once('.').unwrap();
+        assert_eq!(verify_at(SECRET, payload, DAY, 1000), None);
+        assert_eq!(verify_at(SECRET, "alice", DAY, 1000), None);
+    }
+
+    #[test]
+    fn token_of_another_secret_is_rejected() {
+        let token = issue_at(b"other", "alice", 1000);
+        assert_eq!(verify_at(SECRET, &token, DAY, 1000), None);
+    }
+
+    #[test]
+    fn token_expires_after_its_ttl() {
+        let token = issue_at(SECRET, "alice", 1000);
+        let last = 1000 + DAY.as_secs();
+        assert_eq!(
+            verify_at(SECRET, &token, DAY, last),
+            Some("alice".to_string())
+        );
+        assert_eq!(verify_at(SECRET, &token, DAY, last + 1), None);
+        // issued in the future, from a clock we don't trust
+        assert_eq!(verify_at(SECRET, &token, DAY, 999), None);
+    }
+}
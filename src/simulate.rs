@@ -0,0 +1,266 @@
+use crate::bot::{board_view, pick_shots, BotLevel};
+use crate::engine::{Action, GameCore, Point2d};
+use crate::rules::{random_fleet, GameMode, RuleSet};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const USAGE: &str = "usage: battleship simulate <easy|medium|hard> <easy|medium|hard> \
+[--games N] [--seed S] [--rules JSON]";
+
+/// Side names inside simulated games
+const A: &str = "a";
+const B: &str = "b";
+
+/// Offline bot-vs-bot run, `battleship simulate hard medium --games 1000 --seed 7`
+pub fn run(args: &[String]) {
+    let options = match Options::parse(args) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n{}", e, USAGE);
+            return;
+        }
+    };
+    if let Err(e) = options.rules.validate() {
+        println!("Invalid rules: {}", e);
+        return;
+    }
+
+    let mut rng = StdRng::seed_from_u64(options.seed);
+    let mut report = Report::default();
+    for i in 0..options.games {
+        // sides take turns to move first
+        let first = if i % 2 == 0 { A } else { B };
+        report.add(play(&options, first, &mut rng));
+    }
+
+    report.print(&options);
+}
+
+struct Options {
+    a: BotLevel,
+    b: BotLevel,
+    games: usize,
+    seed: u64,
+    rules: RuleSet,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut levels = vec![];
+        let mut options = Options {
+            a: BotLevel::Hard,
+            b: BotLevel::Hard,
+            games: 1000,
+            seed: 42,
+            rules: RuleSet::classic(),
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--games" => options.games = value()?.parse().map_err(|_| "bad --games")?,
+                "--seed" => options.seed = value()?.parse().map_err(|_| "bad --seed")?,
+                "--rules" => {
+                    options.rules = serde_json::from_str(value()?).map_err(|e| e.to_string())?
+                }
+                level => levels.push(
+                    serde_json::from_value(serde_json::Value::String(level.to_string()))
+                        .map_err(|_| format!("unknown strategy {}", level))?,
+                ),
+            }
+        }
+
+        match levels.as_slice() {
+            [a, b] => {
+                options.a = *a;
+                options.b = *b;
+            }
+            _ => return Err("pick two strategies".to_string()),
+        }
+        if options.games == 0 {
+            return Err("--games must be positive".to_string());
+        }
+        Ok(options)
+    }
+
+    fn level(&self, side: &str) -> BotLevel {
+        if side == A {
+            self.a
+        } else {
+            self.b
+        }
+    }
+}
+
+/// Winner and the shots the winner needed, None if no fleet fits or the game got stuck
+fn play(options: &Options, first: &str, rng: &mut StdRng) -> Option<(String, usize)> {
+    let rules = &options.rules;
+    let mut game = GameCore::new(rules.clone(), rng.random()).with_first(first);
+    for side in [A, B] {
+        let join = Action::Join {
+            player: side.to_string(),
+            ships: random_fleet(rules, rng).ok()?,
+        };
+        game.apply(join).ok()?;
+    }
+
+    // every turn fires at least one new cell
+    let max_turns = 2 * rules.width * rules.height + 2;
+    for _ in 0..max_turns {
+        if let Some(result) = &game.result {
+            return Some((result.winner.clone(), result.shots[&result.winner]));
+        }
+
+        let shooter = game.current_turn.clone();
+        let enemy = game.opponent_of(&shooter)?;
+        let count = match rules.mode {
+            GameMode::Classic => 1,
+            GameMode::Salvo => game.player(&shooter)?.alive_ships(),
+        };
+        let shots: Vec<Point2d> = pick_shots(
+            &board_view(enemy),
+            rules,
+            options.level(&shooter),
+            count,
+            rng,
+        );
+        let action = match rules.mode {
+            GameMode::Classic => Action::Shoot {
+                player: shooter,
+                at: *shots.first()?,
+            },
+            GameMode::Salvo => Action::Salvo {
+                player: shooter,
+                shots,
+            },
+        };
+        game.apply(action).ok()?;
+    }
+    None
+}
+
+#[derive(Default)]
+struct Report {
+    wins_a: usize,
+    wins_b: usize,
+    stuck: usize,
+    shots_a: usize,
+    shots_b: usize,
+}
+
+impl Report {
+    fn add(&mut self, outcome: Option<(String, usize)>) {
+        match outcome {
+            Some((winner, shots)) if winner == A => {
+                self.wins_a += 1;
+                self.shots_a += shots;
+            }
+            Some((_, shots)) => {
+                self.wins_b += 1;
+                self.shots_b += shots;
+            }
+            None => self.stuck += 1,
+        }
+    }
+
+    fn print(&self, options: &Options) {
+        let decided = self.wins_a + self.wins_b;
+        println!(
+            "{} games, seed {}, {:?} (a) vs {:?} (b)",
+            options.games, options.seed, options.a, options.b
+        );
+        if decided == 0 {
+            println!("no game was decided");
+            return;
+        }
+
+        let (low, high) = wilson_interval(self.wins_a, decided);
+        println!(
+            "a win rate {:.1}% (95% CI {:.1}%..{:.1}%)",
+            100.0 * self.wins_a as f64 / decided as f64,
+            100.0 * low,
+            100.0 * high
+        );
+        println!(
+            "avg shots to win: a {}, b {}",
+            average(self.shots_a, self.wins_a),
+            average(self.shots_b, self.wins_b)
+        );
+        if self.stuck > 0 {
+            println!("{} games did not finish", self.stuck);
+        }
+    }
+}
+
+fn average(total: usize, count: usize) -> String {
+    if count == 0 {
+        return "-".to_string();
+    }
+    format!("{:.2}", total as f64 / count as f64)
+}
+
+/// 95% Wilson score interval of a win rate, stays inside 0..1 even for lopsided results
+fn wilson_interval(wins: usize, games: usize) -> (f64, f64) {
+    const Z: f64 = 1.96;
+    let n = games as f64;
+    let p = wins as f64 / n;
+    let center = (p + Z * Z / (2.0 * n)) / (1.0 + Z * Z / n);
+    let margin = Z * (p * (1.0 - p) / n + Z * Z / (4.0 * n * n)).sqrt() / (1.0 + Z * Z / n);
+    ((center - margin).max(0.0), (center + margin).min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, String> {
+        let args: Vec<String> = args.split_whitespace().map(String::from).collect();
+        Options::parse(&args)
+    }
+
+    fn assert_interval(wins: usize, games: usize, expected: (f64, f64)) {
+        let (low, high) = wilson_interval(wins, games);
+        assert!(
+            (low - expected.0).abs() < 1e-4 && (high - expected.1).abs() < 1e-4,
+            "{}/{}: {:?}",
+            wins,
+            games,
+            (low, high)
+        );
+    }
+
+    #[test]
+    fn wilson_interval_matches_known_values() {
+        assert_interval(50, 100, (0.4038, 0.5962));
+        assert_interval(81, 263, (0.2553, 0.3662));
+        // stays inside 0..1 at the edges
+        assert_interval(0, 10, (0.0, 0.2775));
+        assert_interval(10, 10, (0.7225, 1.0));
+    }
+
+    #[test]
+    fn parses_strategies_and_flags() {
+        let options = parse("easy hard --games 20 --seed 7").unwrap();
+        assert_eq!((options.a, options.b), (BotLevel::Easy, BotLevel::Hard));
+        assert_eq!((options.games, options.seed), (20, 7));
+        assert_eq!(options.rules, RuleSet::classic());
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        for args in [
+            "",
+            "hard",
+            "hard medium easy",
+            "hard genius",
+            "hard medium --games",
+            "hard medium --games many",
+            "hard medium --games 0",
+            "hard medium --seed -1",
+            "hard medium --rules {",
+        ] {
+            assert!(parse(args).is_err(), "{:?} accepted", args);
+        }
+    }
+}
@@ -0,0 +1,760 @@
+//! Where games outlive the process. `MyState` stays the live copy, storage gets every
+//! change through the `Writer` and hands the running games back on boot.
+
+use crate::bot::BotLevel;
+use crate::dto::{ClientId, GameId, GameResult};
+use crate::engine::GameEvent;
+use crate::rules::RuleSet;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Finished games the in-memory storage keeps for `/replay/{id}`
+const MAX_REPLAYS: usize = 100;
+/// Decided rounds the in-memory storage keeps for `/player/{id}/results`
+const MAX_RESULTS: usize = 1000;
+
+/// Everything needed to rebuild a running game, see `GameCore::restore`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedGame {
+    pub id: GameId,
+    /// Unix seconds
+    pub created: u64,
+    pub rules: RuleSet,
+    pub seed: u64,
+    /// Server side bot holding a seat
+    pub bot: Option<(ClientId, BotLevel)>,
+    pub log: Vec<GameEvent>,
+    /// Chess clocks in ms, see `GameCore::clocks`
+    #[serde(default)]
+    pub clocks: HashMap<ClientId, u64>,
+}
+
+/// A decided round of a game
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishedGame {
+    pub game_id: GameId,
+    pub round: u32,
+    /// Unix seconds
+    pub finished: u64,
+    pub result: GameResult,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerRecord {
+    pub player: ClientId,
+    pub played: u32,
+    pub wins: u32,
+    pub losses: u32,
+}
+
+pub trait Storage: Send + Sync + fmt::Debug {
+    /// Stores a new game with its log so far, replaces an earlier save
+    fn save_game(&self, game: &SavedGame) -> Result<(), String>;
+    /// Events the game produced since the last save
+    fn append_events(&self, game_id: &str, events: &[GameEvent]) -> Result<(), String>;
+    /// Chess clocks after the last move, replaces the earlier ones
+    fn save_clocks(&self, game_id: &str, clocks: &HashMap<ClientId, u64>) -> Result<(), String>;
+    /// Games that were still on the server, oldest first
+    fn load_games(&self) -> Result<Vec<SavedGame>, String>;
+    /// A decided round, kept as it is and counted in both players' records
+    fn record_result(&self, finished: &FinishedGame) -> Result<(), String>;
+    fn player(&self, player: &str) -> Result<Option<PlayerRecord>, String>;
+    /// The player's last decided rounds, newest first
+    fn results(&self, player: &str, limit: usize) -> Result<Vec<FinishedGame>, String>;
+    /// The game left the server, the notation of a finished one is kept
+    fn close_game(&self, game_id: &str, notation: Option<&str>) -> Result<(), String>;
+    fn replay(&self, game_id: &str) -> Result<Option<String>, String>;
+    /// The id belongs to a stored game, running or kept as a replay
+    fn has_game(&self, game_id: &str) -> Result<bool, String>;
+}
+
+/// A change for storage, see `Writer`
+#[derive(Debug)]
+pub enum Write {
+    Game(SavedGame),
+    Events(GameId, Vec<GameEvent>),
+    Clocks(GameId, HashMap<ClientId, u64>),
+    Result(FinishedGame),
+    /// The game left the server, with the notation of a finished one
+    Close(GameId, Option<String>),
+}
+
+impl Write {
+    fn apply(&self, storage: &dyn Storage) -> Result<(), String> {
+        match self {
+            Write::Game(game) => storage
+                .save_game(game)
+                .map_err(|e| format!("game {} not saved: {}", game.id, e)),
+            Write::Events(game_id, events) => storage
+                .append_events(game_id, events)
+                .map_err(|e| format!("game {} not saved: {}", game_id, e)),
+            Write::Clocks(game_id, clocks) => storage
+                .save_clocks(game_id, clocks)
+                .map_err(|e| format!("game {} clocks not saved: {}", game_id, e)),
+            Write::Result(finished) => storage
+                .record_result(finished)
+                .map_err(|e| format!("game {} result not recorded: {}", finished.game_id, e)),
+            Write::Close(game_id, notation) => storage
+                .close_game(game_id, notation.as_deref())
+                .map_err(|e| format!("game {} not closed: {}", game_id, e)),
+        }
+    }
+}
+
+/// Queues writes for a thread of their own, so storage never runs under the state lock.
+/// Writes are applied in the order they were sent, failures are only logged.
+#[derive(Debug, Clone)]
+pub struct Writer {
+    sender: mpsc::Sender<Write>,
+}
+
+impl Writer {
+    pub fn spawn(storage: Arc<dyn Storage>) -> Self {
+        let (sender, receiver) = mpsc::channel::<Write>();
+        thread::spawn(move || {
+            for write in receiver {
+                if let Err(e) = write.apply(storage.as_ref()) {
+                    println!("Storage: {}", e);
+                }
+            }
+        });
+        Self { sender }
+    }
+
+    /// Never blocks
+    pub fn send(&self, write: Write) {
+        let _ = self.sender.send(write);
+    }
+}
+
+/// Lives as long as the process, the default without `DATABASE`
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    inner: Mutex<Memory>,
+}
+
+#[derive(Debug, Default)]
+struct Memory {
+    games: HashMap<GameId, SavedGame>,
+    players: HashMap<ClientId, PlayerRecord>,
+    /// Oldest first
+    results: VecDeque<FinishedGame>,
+    /// Oldest first
+    replays: VecDeque<(GameId, String)>,
+}
+
+impl Storage for MemoryStorage {
+    fn save_game(&self, game: &SavedGame) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.games.insert(game.id.clone(), game.clone());
+        Ok(())
+    }
+
+    fn append_events(&self, game_id: &str, events: &[GameEvent]) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        let game = inner
+            .games
+            .get_mut(game_id)
+            .ok_or(format!("game {} was never saved", game_id))?;
+        game.log.extend(events.iter().cloned());
+        Ok(())
+    }
+
+    fn save_clocks(&self, game_id: &str, clocks: &HashMap<ClientId, u64>) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        let game = inner
+            .games
+            .get_mut(game_id)
+            .ok_or(format!("game {} was never saved", game_id))?;
+        game.clocks = clocks.clone();
+        Ok(())
+    }
+
+    fn load_games(&self) -> Result<Vec<SavedGame>, String> {
+        let inner = self.inner.lock().unwrap();
+        let mut games: Vec<SavedGame> = inner.games.values().cloned().collect();
+        games.sort_by_key(|g| g.created);
+        Ok(games)
+    }
+
+    fn record_result(&self, finished: &FinishedGame) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.results.len() == MAX_RESULTS {
+            inner.results.pop_front();
+        }
+        inner.results.push_back(finished.clone());
+
+        let result = &finished.result;
+        for (player, won) in [(&result.winner, true), (&result.loser, false)] {
+            let record = inner
+                .players
+                .entry(player.clone())
+                .or_insert_with(|| PlayerRecord {
+                    player: player.clone(),
+                    ..PlayerRecord::default()
+                });
+            record.played += 1;
+            if won {
+                record.wins += 1;
+            } else {
+                record.losses += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn player(&self, player: &str) -> Result<Option<PlayerRecord>, String> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.players.get(player).cloned())
+    }
+
+    fn results(&self, player: &str, limit: usize) -> Result<Vec<FinishedGame>, String> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .results
+            .iter()
+            .rev()
+            .filter(|f| f.result.winner == player || f.result.loser == player)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    fn close_game(&self, game_id: &str, notation: Option<&str>) -> Result<(), String> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.games.remove(game_id);
+        if let Some(notation) = notation {
+            if inner.replays.len() == MAX_REPLAYS {
+                inner.replays.pop_front();
+            }
+            inner
+                .replays
+                .push_back((game_id.to_string(), notation.to_string()));
+        }
+        Ok(())
+    }
+
+    fn replay(&self, game_id: &str) -> Result<Option<String>, String> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner
+            .replays
+            .iter()
+            .find(|(id, _)| id == game_id)
+            .map(|(_, text)| text.clone()))
+    }
+
+    fn has_game(&self, game_id: &str) -> Result<bool, String> {
+        let inner = self.inner.lock().unwrap();
+        Ok(inner.games.contains_key(game_id) || inner.replays.iter().any(|(id, _)| id == game_id))
+    }
+}
+
+/// One file, running games are kept as their event log
+#[derive(Debug)]
+pub struct SqliteStorage {
+    db: Mutex<Connection>,
+}
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS games (
+    id TEXT PRIMARY KEY,
+    created INTEGER NOT NULL,
+    rules TEXT NOT NULL,
+    seed INTEGER NOT NULL,
+    bot TEXT
+);
+CREATE TABLE IF NOT EXISTS game_events (
+    game_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    event TEXT NOT NULL,
+    PRIMARY KEY (game_id, seq)
+);
+CREATE TABLE IF NOT EXISTS game_clocks (
+    game_id TEXT NOT NULL,
+    player TEXT NOT NULL,
+    left_ms INTEGER NOT NULL,
+    PRIMARY KEY (game_id, player)
+);
+CREATE TABLE IF NOT EXISTS players (
+    player TEXT PRIMARY KEY,
+    played INTEGER NOT NULL,
+    wins INTEGER NOT NULL,
+    losses INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS results (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    game_id TEXT NOT NULL,
+    round INTEGER NOT NULL,
+    finished INTEGER NOT NULL,
+    winner TEXT NOT NULL,
+    loser TEXT NOT NULL,
+    result TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS results_winner ON results (winner);
+CREATE INDEX IF NOT EXISTS results_loser ON results (loser);
+CREATE TABLE IF NOT EXISTS replays (
+    game_id TEXT PRIMARY KEY,
+    notation TEXT NOT NULL
+);
+";
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let db = Connection::open(path).map_err(|e| e.to_string())?;
+        db.execute_batch(SCHEMA).map_err(|e| e.to_string())?;
+        Ok(Self { db: Mutex::new(db) })
+    }
+}
+
+fn json<T: Serialize>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| e.to_string())
+}
+
+fn from_json<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    serde_json::from_str(text).map_err(|e| e.to_string())
+}
+
+/// Replaces the game's chess clocks
+fn write_clocks(
+    tx: &Transaction,
+    game_id: &str,
+    clocks: &HashMap<ClientId, u64>,
+) -> Result<(), String> {
+    tx.execute("DELETE FROM game_clocks WHERE game_id = ?1", [game_id])
+        .map_err(|e| e.to_string())?;
+    for (player, left) in clocks {
+        tx.execute(
+            "INSERT INTO game_clocks (game_id, player, left_ms) VALUES (?1, ?2, ?3)",
+            params![game_id, player, *left as i64],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn save_game(&self, game: &SavedGame) -> Result<(), String> {
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "INSERT OR REPLACE INTO games (id, created, rules, seed, bot) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                game.id,
+                game.created as i64,
+                json(&game.rules)?,
+                // bit for bit, sqlite integers are signed
+                game.seed as i64,
+                game.bot.as_ref().map(json).transpose()?,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM game_events WHERE game_id = ?1", [&game.id])
+            .map_err(|e| e.to_string())?;
+        for (seq, event) in game.log.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO game_events (game_id, seq, event) VALUES (?1, ?2, ?3)",
+                params![game.id, seq as i64, json(event)?],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        write_clocks(&tx, &game.id, &game.clocks)?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn append_events(&self, game_id: &str, events: &[GameEvent]) -> Result<(), String> {
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        let next: i64 = tx
+            .query_row(
+                "SELECT COALESCE(MAX(seq) + 1, 0) FROM game_events WHERE game_id = ?1",
+                [game_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        for (seq, event) in (next..).zip(events) {
+            tx.execute(
+                "INSERT INTO game_events (game_id, seq, event) VALUES (?1, ?2, ?3)",
+                params![game_id, seq, json(event)?],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn save_clocks(&self, game_id: &str, clocks: &HashMap<ClientId, u64>) -> Result<(), String> {
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        let saved: bool = tx
+            .query_row(
+                "SELECT EXISTS (SELECT 1 FROM games WHERE id = ?1)",
+                [game_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if !saved {
+            return Err(format!("game {} was never saved", game_id));
+        }
+        write_clocks(&tx, game_id, clocks)?;
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn load_games(&self) -> Result<Vec<SavedGame>, String> {
+        let db = self.db.lock().unwrap();
+        let mut games = db
+            .prepare("SELECT id, created, rules, seed, bot FROM games ORDER BY created")
+            .map_err(|e| e.to_string())?;
+        let rows = games
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut events = db
+            .prepare("SELECT event FROM game_events WHERE game_id = ?1 ORDER BY seq")
+            .map_err(|e| e.to_string())?;
+        let mut clocks = db
+            .prepare("SELECT player, left_ms FROM game_clocks WHERE game_id = ?1")
+            .map_err(|e| e.to_string())?;
+        let mut saved = vec![];
+        for row in rows {
+            let (id, created, rules, seed, bot) = row.map_err(|e| e.to_string())?;
+            let log = events
+                .query_map([&id], |row| row.get::<_, String>(0))
+                .map_err(|e| e.to_string())?
+                .map(|event| from_json(&event.map_err(|e| e.to_string())?))
+                .collect::<Result<Vec<GameEvent>, String>>()?;
+            let clocks = clocks
+                .query_map([&id], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))
+                .map_err(|e| e.to_string())?
+                .collect::<Result<HashMap<ClientId, u64>, _>>()
+                .map_err(|e| e.to_string())?;
+            saved.push(SavedGame {
+                id,
+                created: created as u64,
+                rules: from_json(&rules)?,
+                seed: seed as u64,
+                bot: bot.as_deref().map(from_json).transpose()?,
+                log,
+                clocks,
+            });
+        }
+        Ok(saved)
+    }
+
+    fn record_result(&self, finished: &FinishedGame) -> Result<(), String> {
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        let result = &finished.result;
+        tx.execute(
+            "INSERT INTO results (game_id, round, finished, winner, loser, result)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                finished.game_id,
+                finished.round,
+                finished.finished as i64,
+                result.winner,
+                result.loser,
+                json(result)?,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        for (player, won) in [(&result.winner, 1), (&result.loser, 0)] {
+            tx.execute(
+                "INSERT INTO players (player, played, wins, losses) VALUES (?1, 1, ?2, 1 - ?2)
+                 ON CONFLICT (player) DO UPDATE SET
+                     played = played + 1, wins = wins + ?2, losses = losses + 1 - ?2",
+                params![player, won],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn player(&self, player: &str) -> Result<Option<PlayerRecord>, String> {
+        let db = self.db.lock().unwrap();
+        db.query_row(
+            "SELECT played, wins, losses FROM players WHERE player = ?1",
+            [player],
+            |row| {
+                Ok(PlayerRecord {
+                    player: player.to_string(),
+                    played: row.get(0)?,
+                    wins: row.get(1)?,
+                    losses: row.get(2)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())
+    }
+
+    fn results(&self, player: &str, limit: usize) -> Result<Vec<FinishedGame>, String> {
+        let db = self.db.lock().unwrap();
+        let mut results = db
+            .prepare(
+                "SELECT game_id, round, finished, result FROM results
+                 WHERE winner = ?1 OR loser = ?1 ORDER BY id DESC LIMIT ?2",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = results
+            .query_map(params![player, limit as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u32>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })
+            .map_err(|e| e.to_string())?;
+        let mut finished = vec![];
+        for row in rows {
+            let (game_id, round, at, result) = row.map_err(|e| e.to_string())?;
+            finished.push(FinishedGame {
+                game_id,
+                round,
+                finished: at as u64,
+                result: from_json(&result)?,
+            });
+        }
+        Ok(finished)
+    }
+
+    fn close_game(&self, game_id: &str, notation: Option<&str>) -> Result<(), String> {
+        let mut db = self.db.lock().unwrap();
+        let tx = db.transaction().map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM game_events WHERE game_id = ?1", [game_id])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM game_clocks WHERE game_id = ?1", [game_id])
+            .map_err(|e| e.to_string())?;
+        tx.execute("DELETE FROM games WHERE id = ?1", [game_id])
+            .map_err(|e| e.to_string())?;
+        if let Some(notation) = notation {
+            tx.execute(
+                "INSERT OR REPLACE INTO replays (game_id, notation) VALUES (?1, ?2)",
+                params![game_id, notation],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn replay(&self, game_id: &str) -> Result<Option<String>, String> {
+        let db = self.db.lock().unwrap();
+        db.query_row(
+            "SELECT notation FROM replays WHERE game_id = ?1",
+            [game_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())
+    }
+
+    fn has_game(&self, game_id: &str) -> Result<bool, String> {
+        let db = self.db.lock().unwrap();
+        db.query_row(
+            "SELECT EXISTS (SELECT 1 FROM games WHERE id = ?1)
+                 OR EXISTS (SELECT 1 FROM replays WHERE game_id = ?1)",
+            [game_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dto::GameOverReason;
+    use crate::rules::tests::classic_fleet;
+
+    /// Every backend, each test runs against all of them
+    fn backends() -> Vec<Box<dyn Storage>> {
+        vec![
+            Box::new(MemoryStorage::default()),
+            Box::new(SqliteStorage::open(":memory:").unwrap()),
+        ]
+    }
+
+    fn saved(id: &str, created: u64) -> SavedGame {
+        SavedGame {
+            id: id.to_string(),
+            created,
+            rules: RuleSet::classic(),
+            // above i64::MAX, sqlite has to keep the bits
+            seed: u64::MAX - 7,
+            bot: Some(("bot".to_string(), BotLevel::Easy)),
+            log: vec![
+                GameEvent::Placed {
+                    player: "a".to_string(),
+                    ships: classic_fleet(),
+                },
+                GameEvent::Joined {
+                    player: "a".to_string(),
+                },
+            ],
+            clocks: HashMap::from([("a".to_string(), 61_500)]),
+        }
+    }
+
+    fn result(game_id: &str, winner: &str, loser: &str) -> FinishedGame {
+        FinishedGame {
+            game_id: game_id.to_string(),
+            round: 1,
+            finished: 100,
+            result: GameResult {
+                winner: winner.to_string(),
+                loser: loser.to_string(),
+                reason: GameOverReason::Resignation,
+                shots: HashMap::from([(winner.to_string(), 17)]),
+            },
+        }
+    }
+
+    #[test]
+    fn saved_games_load_back_oldest_first() {
+        for storage in backends() {
+            let (older, newer) = (saved("older", 10), saved("newer", 20));
+            storage.save_game(&newer).unwrap();
+            storage.save_game(&older).unwrap();
+            assert_eq!(
+                storage.load_games().unwrap(),
+                vec![older, newer],
+                "{:?}",
+                storage
+            );
+        }
+    }
+
+    #[test]
+    fn appended_events_keep_their_order() {
+        for storage in backends() {
+            let mut game = saved("g", 1);
+            storage.save_game(&game).unwrap();
+            let events = vec![
+                GameEvent::Started {
+                    round: 1,
+                    first: "a".to_string(),
+                },
+                GameEvent::TurnPassed {
+                    to: "bot".to_string(),
+                },
+            ];
+            storage.append_events("g", &events[..1]).unwrap();
+            storage.append_events("g", &events[1..]).unwrap();
+            game.log.extend(events);
+            assert_eq!(storage.load_games().unwrap(), vec![game], "{:?}", storage);
+        }
+    }
+
+    #[test]
+    fn resave_replaces_the_log() {
+        for storage in backends() {
+            let mut game = saved("g", 1);
+            storage.save_game(&game).unwrap();
+            game.log.truncate(1);
+            storage.save_game(&game).unwrap();
+            assert_eq!(storage.load_games().unwrap(), vec![game], "{:?}", storage);
+        }
+    }
+
+    #[test]
+    fn closed_game_keeps_only_its_replay() {
+        for storage in backends() {
+            storage.save_game(&saved("done", 1)).unwrap();
+            storage.save_game(&saved("left", 2)).unwrap();
+            storage.close_game("done", Some("P1 wins")).unwrap();
+            storage.close_game("left", None).unwrap();
+
+            assert!(storage.load_games().unwrap().is_empty(), "{:?}", storage);
+            assert_eq!(storage.replay("done").unwrap().as_deref(), Some("P1 wins"));
+            assert_eq!(storage.replay("left").unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn game_ids_stay_taken_while_stored() {
+        for storage in backends() {
+            storage.save_game(&saved("running", 1)).unwrap();
+            storage.save_game(&saved("done", 2)).unwrap();
+            storage.close_game("done", Some("P1 wins")).unwrap();
+
+            assert!(storage.has_game("running").unwrap(), "{:?}", storage);
+            assert!(storage.has_game("done").unwrap(), "{:?}", storage);
+            assert!(!storage.has_game("other").unwrap(), "{:?}", storage);
+        }
+    }
+
+    #[test]
+    fn results_add_up_per_player() {
+        for storage in backends() {
+            storage.record_result(&result("g1", "a", "b")).unwrap();
+            storage.record_result(&result("g2", "b", "a")).unwrap();
+            storage.record_result(&result("g3", "a", "c")).unwrap();
+
+            let record = |wins, losses| PlayerRecord {
+                player: "a".to_string(),
+                played: wins + losses,
+                wins,
+                losses,
+            };
+            assert_eq!(storage.player("a").unwrap(), Some(record(2, 1)));
+            assert_eq!(storage.player("c").unwrap().map(|r| r.losses), Some(1));
+            assert_eq!(storage.player("d").unwrap(), None, "{:?}", storage);
+        }
+    }
+
+    #[test]
+    fn finished_results_load_back_newest_first() {
+        for storage in backends() {
+            let (first, second, other) = (
+                result("g1", "a", "b"),
+                result("g2", "b", "a"),
+                result("g3", "c", "d"),
+            );
+            let mut rematch = first.clone();
+            rematch.round = 2;
+            for finished in [&first, &second, &other, &rematch] {
+                storage.record_result(finished).unwrap();
+            }
+
+            let expected = vec![rematch.clone(), second.clone(), first.clone()];
+            assert_eq!(storage.results("a", 10).unwrap(), expected, "{:?}", storage);
+            assert_eq!(storage.results("a", 2).unwrap(), expected[..2].to_vec());
+            assert_eq!(storage.results("d", 10).unwrap(), vec![other.clone()]);
+            assert!(storage.results("e", 10).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn clocks_follow_the_last_move() {
+        for storage in backends() {
+            let mut game = saved("g", 1);
+            storage.save_game(&game).unwrap();
+            game.clocks = HashMap::from([("a".to_string(), 30_000), ("bot".to_string(), 5)]);
+            storage.save_clocks("g", &game.clocks).unwrap();
+            assert_eq!(storage.load_games().unwrap(), vec![game.clone()]);
+
+            // a closed game takes its clocks along
+            storage.close_game("g", None).unwrap();
+            assert!(
+                storage.save_clocks("g", &game.clocks).is_err(),
+                "{:?}",
+                storage
+            );
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use crate::app_state::{Client, Wrapper};
+use crate::dto::{
+    ClientId, CreateGameRequest, GameResult, GameStatus, JoinGameRequest, ShipsRaw, WsEvent,
+};
+use crate::engine::RuleError;
+use crate::error::ServerError;
+use crate::game_engine::{game_join, game_new, notify_players};
+use crate::rules::{random_fleet, validate_fleet, RuleSet, TimeoutPolicy, TurnTimer};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Sender;
+
+/// Move limit for tournament games without a turn timer, a stuck bot forfeits
+const DEFAULT_TURN_SECONDS: u64 = 10;
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// Time a bot gets to answer `FleetRq`
+const FLEET_SECONDS: u64 = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TournamentRequest {
+    pub admin_token: String,
+    /// Games every pair of bots plays, seats alternate between them
+    pub games_per_pair: u32,
+    pub rules: RuleSet,
+}
+
+impl Default for TournamentRequest {
+    fn default() -> Self {
+        Self {
+            admin_token: String::new(),
+            games_per_pair: 2,
+            rules: RuleSet::classic(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Standing {
+    pub bot: ClientId,
+    pub played: u32,
+    pub wins: u32,
+    pub losses: u32,
+    /// Shots fired in won games, fewer is better on equal wins
+    pub winning_shots: usize,
+}
+
+/// Fleet a bot was asked for, `ships` stays empty until it answers
+#[derive(Debug)]
+pub struct FleetRequest {
+    rules: RuleSet,
+    ships: Option<ShipsRaw>,
+}
+
+/// Round robin table, also kept while the tournament is running
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Standings {
+    pub finished: bool,
+    pub matches_left: usize,
+    /// Games that ended without a result, e.g. the game vanished after a disconnect
+    pub void: u32,
+    pub table: Vec<Standing>,
+}
+
+impl Standings {
+    fn new(bots: &[ClientId], matches: usize) -> Self {
+        Self {
+            finished: false,
+            matches_left: matches,
+            void: 0,
+            table: bots
+                .iter()
+                .map(|bot| Standing {
+                    bot: bot.clone(),
+                    ..Standing::default()
+                })
+                .collect(),
+        }
+    }
+
+    fn record(&mut self, result: Option<&GameResult>) {
+        self.matches_left -= 1;
+        let Some(result) = result else {
+            self.void += 1;
+            return;
+        };
+
+        for row in self.table.iter_mut() {
+            if row.bot == result.winner {
+                row.played += 1;
+                row.wins += 1;
+                row.winning_shots += result.shots.get(&row.bot).copied().unwrap_or_default();
+            } else if row.bot == result.loser {
+                row.played += 1;
+                row.losses += 1;
+            }
+        }
+        self.table.sort_by(|a, b| {
+            b.wins
+                .cmp(&a.wins)
+                .then(avg_shots(a).total_cmp(&avg_shots(b)))
+                .then(a.bot.cmp(&b.bot))
+        });
+    }
+}
+
+fn avg_shots(row: &Standing) -> f64 {
+    if row.wins == 0 {
+        return f64::MAX;
+    }
+    row.winning_shots as f64 / row.wins as f64
+}
+
+impl fmt::Display for Standings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.finished {
+            "finished".to_string()
+        } else {
+            format!("running, {} games left", self.matches_left)
+        };
+        writeln!(f, "Tournament {}", state)?;
+        writeln!(
+            f,
+            "{:>3}  {:<20} {:>6} {:>4} {:>6} {:>9}",
+            "#", "bot", "played", "won", "lost", "avg shots"
+        )?;
+        for (i, row) in self.table.iter().enumerate() {
+            let avg = if row.wins == 0 {
+                "-".to_string()
+            } else {
+                format!("{:.1}", avg_shots(row))
+            };
+            writeln!(
+                f,
+                "{:>3}  {:<20} {:>6} {:>4} {:>6} {:>9}",
+                i + 1,
+                row.bot,
+                row.played,
+                row.wins,
+                row.losses,
+                avg
+            )?;
+        }
+        if self.void > 0 {
+            writeln!(f, "{} games without a result", self.void)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the rules and starts a round robin between the bots in the lobby,
+/// the caller checks the admin token
+pub fn start(wrapper: Wrapper, mut rq: TournamentRequest) -> Result<Standings, String> {
+    // one game decides a match, `games_per_pair` gives the bots more of them
+    rq.rules.best_of = 1;
+    if rq.rules.turn_timer.is_none() {
+        rq.rules.turn_timer = Some(TurnTimer {
+            seconds: DEFAULT_TURN_SECONDS,
+            on_timeout: TimeoutPolicy::Forfeit,
+        });
+    }
+    rq.rules.validate()?;
+    if rq.games_per_pair == 0 {
+        return Err("Every pair has to play at least once".to_string());
+    }
+
+    let (standings, schedule) = {
+        let mut state = wrapper.shared.state.write().unwrap();
+        if state.tournament.as_ref().is_some_and(|t| !t.finished) {
+            return Err("A tournament is already running".to_string());
+        }
+
+        let mut bots: Vec<ClientId> = state.bots.keys().cloned().collect();
+        bots.sort();
+        if bots.len() < 2 {
+            return Err("At least two bots have to be connected".to_string());
+        }
+
+        let mut schedule = vec![];
+        for i in 0..bots.len() {
+            for j in i + 1..bots.len() {
+                for game in 0..rq.games_per_pair {
+                    let (first, second) = if game % 2 == 0 { (i, j) } else { (j, i) };
+                    schedule.push((bots[first].clone(), bots[second].clone()));
+                }
+            }
+        }
+        let standings = Standings::new(&bots, schedule.len());
+        state.tournament = Some(standings.clone());
+        (standings, schedule)
+    };
+
+    let seed = wrapper.next_seed();
+    println!(
+        "Tournament started: {} games, seed {}",
+        schedule.len(),
+        seed
+    );
+    let rules = rq.rules;
+    tokio::spawn(async move {
+        let mut rng = StdRng::seed_from_u64(seed);
+        for (p1, p2) in schedule {
+            let result = play_match(wrapper.clone(), &p1, &p2, &rules, &mut rng).await;
+            let mut state = wrapper.shared.state.write().unwrap();
+            if let Some(standings) = state.tournament.as_mut() {
+                standings.record(result.as_ref());
+            }
+        }
+
+        let mut state = wrapper.shared.state.write().unwrap();
+        if let Some(standings) = state.tournament.as_mut() {
+            standings.finished = true;
+            println!("{}", standings);
+        }
+    });
+
+    Ok(standings)
+}
+
+/// Plays one game between two lobby bots through the regular create/join flow.
+/// Bots place their own fleets, one that does not answer in time gets a random fleet.
+async fn play_match(
+    wrapper: Wrapper,
+    p1: &str,
+    p2: &str,
+    rules: &RuleSet,
+    rng: &mut StdRng,
+) -> Option<GameResult> {
+    let (sender1, sender2) = {
+        let state = wrapper.shared.state.read().unwrap();
+        (state.bots.get(p1)?.clone(), state.bots.get(p2)?.clone())
+    };
+
+    let [ships1, ships2] = request_fleets(&wrapper, [(p1, &sender1), (p2, &sender2)], rules).await;
+    let mut dealt = |bot: &str, ships: Option<ShipsRaw>| {
+        ships.or_else(|| {
+            println!("Tournament: {} placed no fleet, dealing one", bot);
+            random_fleet(rules, rng).ok()
+        })
+    };
+    let (ships1, ships2) = (dealt(p1, ships1)?, dealt(p2, ships2)?);
+    let rs = game_new(
+        wrapper.clone(),
+        CreateGameRequest {
+            username: p1.to_string(),
+            ships: ships1,
+            rules: rules.clone(),
+        },
+    );
+    // a bot that is still seated somewhere gets that game back, it is not ours to remove
+    let Ok(WsEvent::CreateGameRs {
+        game_id,
+        status: GameStatus::WaitingPlayers,
+    }) = rs
+    else {
+        println!("Tournament game not created: {} vs {} {:?}", p1, p2, rs);
+        return None;
+    };
+    let joined = game_join(
+        wrapper.clone(),
+        JoinGameRequest {
+            game_id: game_id.clone(),
+            username: p2.to_string(),
+            ships: ships2,
+        },
+    );
+    if let Err(e) = joined {
+        println!("Tournament game not joined: {} vs {} {}", p1, p2, e);
+        wrapper.remove_game(&game_id);
+        return None;
+    }
+
+    let players = [
+        Client::new(p1.to_string(), sender1),
+        Client::new(p2.to_string(), sender2),
+    ];
+    for (me, opponent) in [(&players[0], p2), (&players[1], p1)] {
+        wrapper.attach_client(&game_id, me.clone());
+        me.send(WsEvent::MatchedRs {
+            game_id: game_id.clone(),
+            opponent_id: opponent.to_string(),
+            bot: true,
+        })
+        .await;
+        me.send(WsEvent::GameStart {
+            game_id: game_id.clone(),
+        })
+        .await;
+    }
+    println!("Tournament game {}: {} vs {}", &game_id, p1, p2);
+    notify_players(wrapper.clone(), &game_id).await;
+
+    // the turn timer makes sure every game ends
+    let result = loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let state = wrapper.shared.state.read().unwrap();
+        match state.games.get(&game_id) {
+            Some(game) if game.core.result.is_some() => break game.core.result.clone(),
+            Some(_) => continue,
+            None => break None,
+        }
+    };
+
+    // bots stay connected for the next game
+    wrapper.remove_game(&game_id);
+    result
+}
+
+/// Sends `FleetRq` to the bots and waits for their answers until the time is up
+async fn request_fleets(
+    wrapper: &Wrapper,
+    bots: [(&str, &Sender<WsEvent>); 2],
+    rules: &RuleSet,
+) -> [Option<ShipsRaw>; 2] {
+    {
+        let mut state = wrapper.shared.state.write().unwrap();
+        for (bot, _) in bots {
+            let request = FleetRequest {
+                rules: rules.clone(),
+                ships: None,
+            };
+            state.fleets.insert(bot.to_string(), request);
+        }
+    }
+    for (_, sender) in bots {
+        let rq = WsEvent::FleetRq {
+            rules: rules.clone(),
+            seconds: FLEET_SECONDS,
+        };
+        let _ = sender.send(rq).await;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(FLEET_SECONDS);
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let mut state = wrapper.shared.state.write().unwrap();
+        let placed = bots
+            .iter()
+            .all(|(bot, _)| state.fleets.get(*bot).is_some_and(|r| r.ships.is_some()));
+        if placed || Instant::now() >= deadline {
+            return bots.map(|(bot, _)| state.fleets.remove(bot).and_then(|r| r.ships));
+        }
+    }
+}
+
+/// Takes a bot's answer to `FleetRq`, a rejected fleet can be fixed until the time is up
+pub fn submit_fleet(wrapper: &Wrapper, bot: &str, ships: ShipsRaw) -> Result<(), ServerError> {
+    let mut state = wrapper.shared.state.write().unwrap();
+    let request = state.fleets.get_mut(bot).ok_or(ServerError::NotInGame)?;
+    if let Err(e) = validate_fleet(&ships, &request.rules) {
+        println!("Rejected placement: {} {}", bot, e);
+        return Err(RuleError::Placement(e).into());
+    }
+    request.ships = Some(ships);
+    Ok(())
+}
+
+/// Both the running and the last finished tournament
+pub fn standings(wrapper: &Wrapper) -> Option<Standings> {
+    let state = wrapper.shared.state.read().unwrap();
+    state.tournament.clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::dto::GameOverReason;
+    use crate::rules::tests::classic_fleet;
+    use crate::storage::MemoryStorage;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use tokio::sync::mpsc;
+
+    fn server() -> Wrapper {
+        let config = ServerConfig {
+            game_seed: Some(1),
+            ..ServerConfig::default()
+        };
+        Wrapper::new(config, Arc::new(MemoryStorage::default()))
+    }
+
+    /// Registers a bot that answers every `FleetRq` with the classic fleet
+    fn placing_bot(wrapper: &Wrapper, name: &'static str) {
+        let (sender, mut receiver) = mpsc::channel(10);
+        assert!(wrapper.register_bot(name, sender));
+        let wrapper = wrapper.clone();
+        tokio::spawn(async move {
+            while let Some(event) = receiver.recv().await {
+                if let WsEvent::FleetRq { .. } = event {
+                    submit_fleet(&wrapper, name, classic_fleet()).unwrap();
+                }
+            }
+        });
+    }
+
+    fn win(winner: &str, loser: &str, shots: usize) -> GameResult {
+        GameResult {
+            winner: winner.to_string(),
+            loser: loser.to_string(),
+            reason: GameOverReason::AllShipsSunk,
+            shots: HashMap::from([(winner.to_string(), shots)]),
+        }
+    }
+
+    fn order(standings: &Standings) -> Vec<&str> {
+        standings.table.iter().map(|row| row.bot.as_str()).collect()
+    }
+
+    #[test]
+    fn standings_rank_by_wins_then_fewer_shots_then_name() {
+        let bots = ["a", "b", "c", "d"].map(String::from);
+        let mut standings = Standings::new(&bots, 6);
+        standings.record(Some(&win("c", "a", 40)));
+        standings.record(Some(&win("c", "b", 40)));
+        standings.record(Some(&win("b", "d", 30)));
+        standings.record(Some(&win("a", "d", 50)));
+        assert_eq!(order(&standings), ["c", "b", "a", "d"]);
+
+        // same wins and the same average, the name decides
+        standings.record(Some(&win("a", "b", 30)));
+        assert_eq!(order(&standings), ["a", "c", "b", "d"]);
+        let a = &standings.table[0];
+        assert_eq!((a.played, a.wins, a.losses, a.winning_shots), (3, 2, 1, 80));
+
+        standings.record(None);
+        assert_eq!((standings.matches_left, standings.void), (0, 1));
+    }
+
+    #[test]
+    fn bots_without_a_win_rank_below_by_name() {
+        let bots = ["c", "b", "a"].map(String::from);
+        let mut standings = Standings::new(&bots, 1);
+        standings.record(Some(&win("c", "b", 99)));
+        assert_eq!(order(&standings), ["c", "a", "b"]);
+    }
+
+    #[tokio::test]
+    async fn bots_place_their_own_fleets() {
+        let wrapper = server();
+        placing_bot(&wrapper, "alpha");
+        placing_bot(&wrapper, "beta");
+        let senders = {
+            let state = wrapper.shared.state.read().unwrap();
+            (state.bots["alpha"].clone(), state.bots["beta"].clone())
+        };
+        let bots = [("alpha", &senders.0), ("beta", &senders.1)];
+
+        let started = Instant::now();
+        let fleets = request_fleets(&wrapper, bots, &RuleSet::classic()).await;
+        assert_eq!(fleets, [Some(classic_fleet()), Some(classic_fleet())]);
+        assert!(started.elapsed() < Duration::from_secs(FLEET_SECONDS));
+        assert!(wrapper.shared.state.read().unwrap().fleets.is_empty());
+    }
+
+    #[test]
+    fn fleet_is_taken_only_when_asked_for_and_legal() {
+        let wrapper = server();
+        let unasked = submit_fleet(&wrapper, "alpha", classic_fleet());
+        assert_eq!(unasked, Err(ServerError::NotInGame));
+
+        let request = FleetRequest {
+            rules: RuleSet::classic(),
+            ships: None,
+        };
+        wrapper
+            .shared
+            .state
+            .write()
+            .unwrap()
+            .fleets
+            .insert("alpha".to_string(), request);
+        let mut short = classic_fleet();
+        short.pop();
+        assert!(matches!(
+            submit_fleet(&wrapper, "alpha", short),
+            Err(ServerError::Rule(RuleError::Placement(_)))
+        ));
+        assert_eq!(submit_fleet(&wrapper, "alpha", classic_fleet()), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn match_leaves_a_game_it_did_not_create() {
+        let wrapper = server();
+        placing_bot(&wrapper, "alpha");
+        placing_bot(&wrapper, "beta");
+        let rq = CreateGameRequest {
+            username: "alpha".to_string(),
+            ships: classic_fleet(),
+            rules: RuleSet::classic(),
+        };
+        let Ok(WsEvent::CreateGameRs { game_id, .. }) = game_new(wrapper.clone(), rq) else {
+            panic!("game not created");
+        };
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let rules = RuleSet::classic();
+        let result = play_match(wrapper.clone(), "alpha", "beta", &rules, &mut rng).await;
+        assert_eq!(result, None);
+        let state = wrapper.shared.state.read().unwrap();
+        assert!(state.games.contains_key(&game_id));
+        assert_eq!(state.client_games.get("alpha"), Some(&game_id));
+    }
+}